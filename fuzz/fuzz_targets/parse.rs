@@ -0,0 +1,12 @@
+//! Feeds arbitrary bytes to every parser that sees untrusted input.
+//!
+//! Run with `cargo fuzz run parse` from the repository root. Any input that
+//! panics goes into `fuzz/regressions`, where the tests in `pngme::fuzzing` replay it.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    pngme::fuzzing::parse_everything(data);
+});
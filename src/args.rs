@@ -1,16 +1,62 @@
 use clap::{
+    ArgGroup,
     Args,
     Parser,
-    Subcommand
+    Subcommand,
+    ValueEnum
 };
+use pngme::png::ParseLimits;
 
 /// Simple program to greet a person
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
+#[command(after_help = "Exit codes: 0 success, 1 other failure, 2 invalid input, 3 not found, 4 corrupt file or limit exceeded")]
 pub struct Cli{
     /// Operation on png file
     #[command(subcommand)]
     pub command : Commands,
+    /// Output format; json prints one object on stdout, errors included
+    #[arg(long, value_enum, global = true, default_value_t = Format::Text)]
+    pub format : Format,
+    #[command(flatten)]
+    pub limits : LimitArgs,
+}
+
+/// Caps on what an input file may make pngme hold in memory.
+#[derive(Args, Debug)]
+#[command(next_help_heading = "Limits")]
+pub struct LimitArgs{
+    /// Reject chunks with more data than this many bytes
+    #[arg(long, global = true, default_value_t = ParseLimits::default().max_chunk_length)]
+    pub max_chunk_length : u32,
+    /// Reject files with more chunks than this
+    #[arg(long, global = true, default_value_t = ParseLimits::default().max_chunk_count)]
+    pub max_chunks : usize,
+    /// Reject files whose ancillary chunks hold more than this many bytes in all
+    #[arg(long, global = true, default_value_t = ParseLimits::default().max_ancillary_bytes)]
+    pub max_ancillary_bytes : u64,
+    /// Refuse to decompress text or a message past this many bytes
+    #[arg(long, visible_alias = "max-size", global = true, default_value_t = ParseLimits::default().max_decompressed_size)]
+    pub max_decompressed_size : u64,
+}
+
+impl LimitArgs{
+    pub fn parse_limits(&self) -> ParseLimits{
+        ParseLimits{
+            max_chunk_length : self.max_chunk_length,
+            max_chunk_count : self.max_chunks,
+            max_ancillary_bytes : self.max_ancillary_bytes,
+            max_decompressed_size : self.max_decompressed_size,
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format{
+    /// Human-readable text
+    Text,
+    /// A JSON object with "ok" and a "result" or "error"
+    Json,
 }
 
 #[derive(Subcommand, Debug)]
@@ -21,41 +67,289 @@ pub enum Commands{
     Decode(DecodeArgs),
     /// Remove the chunk
     Remove(RemoveArgs),
-    /// Print the file
+    /// List every chunk in the file
+    #[command(after_help = "PROPS: C critical or a ancillary, P public or p private, R reserved bit set, S safe or u unsafe to copy")]
     Print(PrintArgs), 
+    /// Generate a key pair for recipient encryption
+    Keygen(KeygenArgs),
+    /// Read and edit the standard tEXt, zTXt and iTXt chunks
+    Text(TextArgs),
+    /// Show the image properties from the IHDR chunk
+    Info(InfoArgs),
+    /// Show, extract or strip data appended after the IEND chunk
+    Trailing(TrailingArgs),
 }
 
 #[derive(Args, Debug)]
+#[command(group(ArgGroup::new("payload").required(true).args(["message", "input_file", "stdin"])))]
 pub struct EncodeArgs{
-    /// File path for the png file 
+    /// File path for the png file ("-" for stdin)
     pub file_path : String, 
     /// Chunk type 
     pub chunk_type : String,
     /// Message to encode
-    pub message : String, 
+    pub message : Option<String>, 
+    /// Encode the contents of this file instead of a message
+    #[arg(long)]
+    pub input_file : Option<String>,
+    /// Encode bytes read from stdin instead of a message
+    #[arg(long)]
+    pub stdin : bool,
+    /// Content type recorded with the message [default: text for a message, binary otherwise]
+    #[arg(long)]
+    pub content_type : Option<String>,
+    /// Compress the message with zlib before embedding it
+    #[arg(long)]
+    pub compress : bool,
+    /// Store the bytes as they are, without the pngme envelope
+    #[arg(long, conflicts_with_all = ["encrypt", "recipient", "content_type", "compress"])]
+    pub raw : bool,
+    /// Encrypt the message with a passphrase (prompted, or taken from $PNGME_PASSPHRASE)
+    #[arg(long)]
+    pub encrypt : bool,
+    /// Encrypt the message to this public key, or to the key in this file (repeatable)
+    #[arg(short, long, conflicts_with = "encrypt")]
+    pub recipient : Vec<String>,
+    /// Split the message across chunks holding at most this many bytes of it
+    #[arg(long)]
+    pub split_size : Option<usize>,
+    /// Split the message across this many chunks
+    #[arg(long, conflicts_with = "split_size")]
+    pub parts : Option<usize>,
+    /// Where to place the new chunk(s)
+    #[arg(long, value_enum, default_value_t = Placement::BeforeIend)]
+    pub position : Placement,
+    /// Place the new chunk at this raw chunk index instead
+    #[arg(long, conflicts_with = "position")]
+    pub index : Option<usize>,
+    /// Write the result here instead of modifying the input ("-" for stdout)
+    #[arg(short, long)]
+    pub output : Option<String>,
+    /// Keep the original file as <FILE_PATH>.bak
+    #[arg(long, conflicts_with = "output")]
+    pub backup : bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Placement{
+    /// Right before IEND
+    BeforeIend,
+    /// Right after IHDR
+    AfterIhdr,
+    /// Right before the first IDAT
+    BeforeIdat,
 }
 
 #[derive(Args, Debug)]
 pub struct DecodeArgs{
-    /// File path for the png file 
+    /// File path for the png file ("-" for stdin)
     pub file_path : String, 
-    /// Chunk type 
-    pub chunk_type : String,
+    /// Chunk type [default: any chunk holding a pngme message]
+    pub chunk_type : Option<String>,
+    /// Decode every message in chunks of this type, or every pngme message without a type
+    #[arg(long)]
+    pub all : bool,
+    /// Decode only the Nth of those messages, counting from 0; a split message counts once
+    #[arg(long, conflicts_with = "all")]
+    pub index : Option<usize>,
+    /// Write the message bytes to this file instead of printing it
+    #[arg(long, conflicts_with = "all")]
+    pub output_file : Option<String>,
+    /// Write the message bytes to stdout as they are
+    #[arg(long, conflicts_with_all = ["all", "output_file"])]
+    pub raw : bool,
+    /// Secret key file for messages encrypted to recipients
+    #[arg(short = 'k', long)]
+    pub identity : Option<String>,
+    /// Keep chunks with a bad CRC instead of rejecting the file
+    #[arg(long)]
+    pub lenient : bool,
 }
 
 #[derive(Args, Debug)]
 pub struct RemoveArgs{
-    /// File path for the png file 
+    /// File path for the png file ("-" for stdin)
     pub file_path : String, 
     /// Chunk type 
     pub chunk_type : String,
+    /// Remove every message in chunks of this type
+    #[arg(long)]
+    pub all : bool,
+    /// Remove the Nth message in chunks of this type, counting from 0; a split message counts once
+    #[arg(long, conflicts_with = "all")]
+    pub index : Option<usize>,
+    /// Write the result here instead of modifying the input ("-" for stdout)
+    #[arg(short, long)]
+    pub output : Option<String>,
+    /// Keep the original file as <FILE_PATH>.bak
+    #[arg(long, conflicts_with = "output")]
+    pub backup : bool,
 }
 
 #[derive(Args, Debug)]
 pub struct PrintArgs{
-    /// File path for the png file 
+    /// File path for the png file ("-" for stdin)
+    pub file_path : String,
+    /// Only list chunks whose type matches this pattern ("?" is any letter, "*" any run of letters)
+    #[arg(long = "type", value_name = "PATTERN")]
+    pub type_pattern : Option<String>,
+    /// Only list chunks with this property (repeatable; all must hold)
+    #[arg(long, value_enum)]
+    pub property : Vec<Property>,
+}
+
+/// The properties encoded in the case of a chunk type's letters.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Property{
+    Critical,
+    Ancillary,
+    Public,
+    Private,
+    /// The reserved bit is set, which no valid chunk does
+    Reserved,
+    SafeToCopy,
+    UnsafeToCopy,
+}
+
+#[derive(Args, Debug)]
+pub struct InfoArgs{
+    /// File path for the png file ("-" for stdin)
     pub file_path : String,
 }
 
+#[derive(Args, Debug)]
+pub struct KeygenArgs{
+    /// File to write the secret key to; the public key is printed
+    pub output : String,
+}
+
+#[derive(Args, Debug)]
+pub struct TextArgs{
+    #[command(subcommand)]
+    pub command : TextCommands,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum TextCommands{
+    /// List every text chunk
+    List(TextListArgs),
+    /// Print the text stored under a keyword
+    Get(TextGetArgs),
+    /// Store text under a keyword, replacing what was there
+    Set(TextSetArgs),
+    /// Delete the text chunks with a keyword
+    Delete(TextDeleteArgs),
+}
 
+#[derive(Args, Debug)]
+pub struct TextListArgs{
+    /// File path for the png file ("-" for stdin)
+    pub file_path : String,
+}
 
+#[derive(Args, Debug)]
+pub struct TextGetArgs{
+    /// File path for the png file ("-" for stdin)
+    pub file_path : String,
+    /// Keyword, case-sensitive
+    pub keyword : String,
+}
+
+#[derive(Args, Debug)]
+pub struct TextSetArgs{
+    /// File path for the png file ("-" for stdin)
+    pub file_path : String,
+    /// Keyword, 1 to 79 Latin-1 characters
+    pub keyword : String,
+    /// Text to store
+    pub text : String,
+    /// Compress the text (zTXt, or a compressed iTXt)
+    #[arg(long)]
+    pub compress : bool,
+    /// Store it as UTF-8 iTXt even if it fits in Latin-1
+    #[arg(long)]
+    pub international : bool,
+    /// Language tag of the text, such as "en-GB" (implies iTXt)
+    #[arg(long)]
+    pub language : Option<String>,
+    /// The keyword translated into the text's language (implies iTXt)
+    #[arg(long)]
+    pub translated_keyword : Option<String>,
+    /// Write the result here instead of modifying the input ("-" for stdout)
+    #[arg(short, long)]
+    pub output : Option<String>,
+    /// Keep the original file as <FILE_PATH>.bak
+    #[arg(long, conflicts_with = "output")]
+    pub backup : bool,
+}
+
+#[derive(Args, Debug)]
+pub struct TextDeleteArgs{
+    /// File path for the png file ("-" for stdin)
+    pub file_path : String,
+    /// Keyword, case-sensitive
+    pub keyword : String,
+    /// Write the result here instead of modifying the input ("-" for stdout)
+    #[arg(short, long)]
+    pub output : Option<String>,
+    /// Keep the original file as <FILE_PATH>.bak
+    #[arg(long, conflicts_with = "output")]
+    pub backup : bool,
+}
+
+#[derive(Args, Debug)]
+pub struct TrailingArgs{
+    #[command(subcommand)]
+    pub command : TrailingCommands,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum TrailingCommands{
+    /// Say how much data follows IEND and preview it
+    Show(TrailingShowArgs),
+    /// Write the data after IEND to a file or stdout
+    Extract(TrailingExtractArgs),
+    /// Remove the data after IEND
+    Strip(TrailingStripArgs),
+    /// Move the chunks found after IEND, where earlier releases put messages, back in front of it
+    Restore(TrailingRestoreArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct TrailingShowArgs{
+    /// File path for the png file ("-" for stdin)
+    pub file_path : String,
+}
+
+#[derive(Args, Debug)]
+pub struct TrailingExtractArgs{
+    /// File path for the png file ("-" for stdin)
+    pub file_path : String,
+    /// Write the bytes here instead of stdout
+    #[arg(short, long)]
+    pub output : Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct TrailingStripArgs{
+    /// File path for the png file ("-" for stdin)
+    pub file_path : String,
+    /// Write the result here instead of modifying the input ("-" for stdout)
+    #[arg(short, long)]
+    pub output : Option<String>,
+    /// Keep the original file as <FILE_PATH>.bak
+    #[arg(long, conflicts_with = "output")]
+    pub backup : bool,
+}
+
+#[derive(Args, Debug)]
+pub struct TrailingRestoreArgs{
+    /// File path for the png file ("-" for stdin)
+    pub file_path : String,
+    /// Write the result here instead of modifying the input ("-" for stdout)
+    #[arg(short, long)]
+    pub output : Option<String>,
+    /// Keep the original file as <FILE_PATH>.bak
+    #[arg(long, conflicts_with = "output")]
+    pub backup : bool,
+}
@@ -0,0 +1,173 @@
+use std::fs::{self, File, Permissions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::Result;
+
+/// Replace the file at `path` with whatever `write` produces, crash-safely.
+///
+/// The new content goes to a temporary file in the same directory, which gets
+/// the original's permissions, is fsynced, and is then renamed over `path`.
+/// If `write` fails the original is left untouched. With `backup` the
+/// original is also kept as `<path>.bak`.
+pub fn replace_file<T, F>(path: &Path, backup: bool, write: F) -> Result<T>
+where
+    F: FnOnce(&mut BufWriter<&File>) -> Result<T>,
+{
+    let permissions = fs::metadata(path)?.permissions();
+    write_through_temp(path, Some(permissions), backup, write)
+}
+
+/// Write whatever `write` produces to `path`, whether or not a file is
+/// there yet, the same crash-safe way as `replace_file`. If `write` fails
+/// nothing is left behind and an existing file is untouched.
+pub fn write_file<T, F>(path: &Path, write: F) -> Result<T>
+where
+    F: FnOnce(&mut BufWriter<&File>) -> Result<T>,
+{
+    let permissions = fs::metadata(path).ok().map(|metadata| metadata.permissions());
+    write_through_temp(path, permissions, false, write)
+}
+
+/// Without `permissions` the file gets the ones a newly created file would.
+fn write_through_temp<T, F>(path: &Path, permissions: Option<Permissions>, backup: bool, write: F) -> Result<T>
+where
+    F: FnOnce(&mut BufWriter<&File>) -> Result<T>,
+{
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    let mut builder = tempfile::Builder::new();
+    builder.prefix(".pngme-").suffix(".tmp");
+    #[cfg(unix)]
+    if permissions.is_none()
+    {
+        // The umask applies, as it does to File::create.
+        use std::os::unix::fs::PermissionsExt;
+        builder.permissions(Permissions::from_mode(0o666));
+    }
+    let tmp = builder.tempfile_in(dir)?;
+
+    let mut writer = BufWriter::new(tmp.as_file());
+    let value = write(&mut writer)?;
+    writer.flush()?;
+    drop(writer);
+
+    if let Some(permissions) = permissions
+    {
+        tmp.as_file().set_permissions(permissions)?;
+    }
+    tmp.as_file().sync_all()?;
+
+    if backup
+    {
+        let bak = backup_path(path);
+        let _ = fs::remove_file(&bak);
+        if fs::hard_link(path, &bak).is_err()
+        {
+            fs::copy(path, &bak)?;
+        }
+    }
+
+    tmp.persist(path).map_err(|err| err.error)?;
+    sync_dir(dir)?;
+    Ok(value)
+}
+
+/// Where `replace_file` keeps the original when asked for a backup.
+pub fn backup_path(path: &Path) -> PathBuf{
+    let mut bak = path.as_os_str().to_owned();
+    bak.push(".bak");
+    PathBuf::from(bak)
+}
+
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> Result<()>{
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> Result<()>{
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::PngError;
+
+    #[test]
+    fn test_replace_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        fs::write(&path, b"old").unwrap();
+
+        replace_file(&path, false, |w| Ok(w.write_all(b"new")?)).unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert!(!backup_path(&path).exists());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_replace_file_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        fs::write(&path, b"old").unwrap();
+
+        replace_file(&path, true, |w| Ok(w.write_all(b"new")?)).unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert_eq!(fs::read(dir.path().join("image.png.bak")).unwrap(), b"old");
+    }
+
+    #[test]
+    fn test_failed_write_keeps_original() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        fs::write(&path, b"old").unwrap();
+
+        let res: Result<()> = replace_file(&path, true, |w| {
+            w.write_all(b"half")?;
+            Err(PngError::MissingChunk{ chunk_type : "IEND".to_string() })
+        });
+
+        assert!(res.is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"old");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_write_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.png");
+        write_file(&path, |w| Ok(w.write_all(b"new")?)).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+
+        let res: Result<()> = write_file(&path, |w| {
+            w.write_all(b"half")?;
+            Err(PngError::MissingChunk{ chunk_type : "IEND".to_string() })
+        });
+        assert!(res.is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        fs::write(&path, b"old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+
+        replace_file(&path, false, |w| Ok(w.write_all(b"new")?)).unwrap();
+
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o640);
+    }
+}
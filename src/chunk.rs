@@ -3,6 +3,7 @@ use std::fmt;
 use crc::Crc; 
 
 use crate::{Error, Result};
+use crate::error::CrcMismatch;
 use crate::chunk_type::ChunkType;
 
 #[derive(Debug)]
@@ -26,21 +27,45 @@ impl fmt::Display for Chunk{
 
 #[allow(unused)]
 impl Chunk{
-    pub fn new(chunk_t: ChunkType, arg_data: Vec<u8>) -> Chunk
-    {
-        let length = arg_data.len(); 
-        let chunkt_bytes = chunk_t.bytes(); 
-        let buff: Vec<u8> = chunkt_bytes.iter().chain(arg_data.iter()).copied().collect(); 
+    /// Largest data length the PNG spec allows in a single chunk, 2^31-1.
+    pub const MAX_LENGTH : u32 = (1 << 31) - 1;
 
-        let crc = Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
-        let crc_sum = crc.checksum(&buff);
+    pub fn new(chunk_t: ChunkType, arg_data: Vec<u8>) -> Result<Chunk>
+    {
+        let length = Chunk::check_length(arg_data.len())?;
+        let crc_sum = Chunk::checksum(&chunk_t, &arg_data);
 
-        Chunk{
-            len : length as u32, 
+        Ok(Chunk{
+            len : length, 
             chunk_type : chunk_t,
             data : arg_data,
             crc : crc_sum,
+        })
+    }
+
+    /// `len` as a chunk length field, or an error if it is over `MAX_LENGTH`.
+    pub fn check_length(len: usize) -> Result<u32>
+    {
+        if len > Chunk::MAX_LENGTH as usize
+        {
+            return Err(Error::LimitExceeded{ limit : "chunk length", max : Chunk::MAX_LENGTH as u64 })
         }
+        Ok(len as u32)
+    }
+
+    /// CRC-32 over the chunk type and data, as the PNG spec defines it.
+    pub fn checksum(chunk_t: &ChunkType, data: &[u8]) -> u32
+    {
+        let crc = Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+        let mut digest = crc.digest();
+        digest.update(&chunk_t.bytes());
+        digest.update(data);
+        digest.finalize()
+    }
+
+    /// Whether the stored CRC matches the chunk type and data.
+    pub fn crc_is_valid(&self) -> bool{
+        Chunk::checksum(&self.chunk_type, &self.data) == self.crc
     }
 
     pub fn length(&self)-> u32{
@@ -60,7 +85,7 @@ impl Chunk{
     }
 
     pub fn data_as_string(&self) -> Result<String>{
-        let string = String::from_utf8(self.data.to_vec()).unwrap(); 
+        let string = String::from_utf8(self.data.to_vec())?; 
         Ok(string)
     }
 
@@ -82,48 +107,69 @@ impl Chunk{
 impl TryFrom<&[u8]> for Chunk{
     type Error = Error;
 
+    /// Parse the chunk at the start of `vec`, length field to CRC. Bytes after
+    /// the CRC are left alone.
     fn try_from(vec : &[u8]) -> Result<Self>
     {   
-        let mut length = 0; 
-        let len_iter = vec[0..4].iter(); 
-        for (i, byte) in len_iter.enumerate()
+        let (chunk, bad_crc) = Chunk::decode(vec, 0, false, |_, _| Ok(()))?;
+        if let Some(expected) = bad_crc
         {
-            length += (*byte as u32) << ((3 - i) * 8);
+            return Err(CrcMismatch{
+                index : 0,
+                chunk_type : chunk.chunk_type.to_string(),
+                offset : 0,
+                expected,
+                actual : chunk.crc,
+            }.into())
         }
+        Ok(chunk)
+    }
+    
+}
 
-        let list : [u8; 4] = vec[4..8].try_into().unwrap();
-        let chunk_t = ChunkType::try_from(list).unwrap();
-
-        let mut arg_data = Vec::new();
-        let data_end = (8 + length) as usize;
-        let data_iter = vec[8..data_end].iter();
-        for i in data_iter{
-            arg_data.push(*i);
+impl Chunk{
+    /// Decode the chunk at the start of `bytes`, which is at `offset` in the
+    /// file, without checking its CRC against the data: the second value is
+    /// the CRC it should have had when the stored one is wrong. `admit` gets
+    /// the type and length before the data is read. With `any_type` the type
+    /// bytes are kept as they are instead of having to be letters.
+    pub(crate) fn decode<F>(bytes: &[u8], offset: usize, any_type: bool, admit: F) -> Result<(Chunk, Option<u32>)>
+    where
+        F: FnOnce(&ChunkType, u32) -> Result<()>,
+    {
+        if bytes.len() < 12
+        {
+            return Err(Error::TruncatedChunk{ offset, needed : 12, available : bytes.len() })
         }
-
-        let mut crc_tmp = 0; 
-        let crc_iter = vec[data_end..].iter(); 
-        for (i, byte) in crc_iter.enumerate()
+        let length = u32::from_be_bytes(bytes[0..4].try_into().unwrap());
+        let chunkt: [u8; 4] = bytes[4..8].try_into().unwrap();
+        let chunk_type = if any_type
         {
-            crc_tmp += (*byte as u32) << ((3 - i) * 8);
+            ChunkType{ sum : u32::from_be_bytes(chunkt) }
         }
+        else
+        {
+            ChunkType::try_from(chunkt).map_err(|_| Error::InvalidChunkType{ bytes : chunkt.to_vec(), offset : Some(offset) })?
+        };
+        admit(&chunk_type, length)?;
 
-        let crc = Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
-        let buff: Vec<u8> = list.iter().chain(arg_data.iter()).copied().collect();
-        let crc_sum = crc.checksum(&buff); 
-        if crc_sum == crc_tmp
+        // Widen before adding so a huge length cannot wrap around.
+        let needed = 12 + length as u64;
+        if (bytes.len() as u64) < needed
         {
-            let chunk = Chunk{
-                len : length,
-                chunk_type : chunk_t,
-                data : arg_data,
-                crc :crc_tmp,
-            };
-            return Ok(chunk)
+            return Err(Error::TruncatedChunk{ offset, needed : needed as usize, available : bytes.len() })
         }
-        Err("Crc doesn't match".into())
+        let data_end = 8 + length as usize;
+        let chunk = Chunk{
+            len : length,
+            chunk_type,
+            data : bytes[8..data_end].to_vec(),
+            crc : u32::from_be_bytes(bytes[data_end..data_end + 4].try_into().unwrap()),
+        };
+        let expected = Chunk::checksum(&chunk.chunk_type, &chunk.data);
+        let bad_crc = (expected != chunk.crc).then_some(expected);
+        Ok((chunk, bad_crc))
     }
-    
 }
 
 #[allow(unused)]
@@ -155,7 +201,7 @@ mod tests {
     fn test_new_chunk() {
         let chunk_type = ChunkType::from_str("RuSt").unwrap();
         let data = "This is where your secret message will be!".as_bytes().to_vec();
-        let chunk = Chunk::new(chunk_type, data);
+        let chunk = Chunk::new(chunk_type, data).unwrap();
         assert_eq!(chunk.length(), 42);
         assert_eq!(chunk.crc(), 2882656334);
     }
@@ -180,6 +226,12 @@ mod tests {
         assert_eq!(chunk_string, expected_chunk_string);
     }
 
+    #[test]
+    fn test_chunk_binary_string() {
+        let chunk = Chunk::new(ChunkType::from_str("RuSt").unwrap(), vec![0xff, 0xfe, 0x00]).unwrap();
+        assert!(chunk.data_as_string().is_err());
+    }
+
     #[test]
     fn test_chunk_crc() {
         let chunk = testing_chunk();
@@ -234,6 +286,27 @@ mod tests {
         assert!(chunk.is_err());
     }
 
+    #[test]
+    fn test_malformed_chunk_from_bytes() {
+        let chunk = testing_chunk().as_bytes();
+        for len in [0, 3, 11, chunk.len() - 1]
+        {
+            assert!(matches!(Chunk::try_from(&chunk[..len]), Err(Error::TruncatedChunk{ .. })), "{} bytes", len);
+        }
+
+        let mut huge = chunk.clone();
+        huge[0..4].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(matches!(Chunk::try_from(huge.as_ref()), Err(Error::TruncatedChunk{ .. })));
+
+        let mut bad_type = chunk.clone();
+        bad_type[6] = b'1';
+        assert!(matches!(Chunk::try_from(bad_type.as_ref()), Err(Error::InvalidChunkType{ .. })));
+
+        let mut longer = chunk.clone();
+        longer.extend_from_slice(b"next chunk");
+        assert_eq!(Chunk::try_from(longer.as_ref()).unwrap().data(), testing_chunk().data());
+    }
+
     #[test]
     pub fn test_chunk_trait_impls() {
         let data_length: u32 = 42;
@@ -254,5 +327,11 @@ mod tests {
         
         let _chunk_string = format!("{}", chunk);
     }
-}
 
+    #[test]
+    fn test_chunk_length_limit() {
+        assert_eq!(Chunk::check_length(42).unwrap(), 42);
+        assert_eq!(Chunk::check_length(Chunk::MAX_LENGTH as usize).unwrap(), Chunk::MAX_LENGTH);
+        assert!(matches!(Chunk::check_length(1 << 31), Err(Error::LimitExceeded{ .. })));
+    }
+}
@@ -5,6 +5,7 @@ use std::str::FromStr;
 use std::str;
 
 use crate::{Error, Result};
+use crate::error::PngError;
 
 
 #[derive(Eq, Debug, PartialEq)]
@@ -61,12 +62,32 @@ impl ChunkType {
     pub fn is_safe_to_copy(&self) -> bool{
         (self.sum & ChunkType::STC_MASK) != 0
     }
+
+    /// Whether the type matches a glob `pattern`, where `?` stands for any one
+    /// letter and `*` for any run of them. Case-sensitive, like chunk types.
+    pub fn matches_pattern(&self, pattern: &str) -> bool{
+        glob(pattern.as_bytes(), &self.bytes())
+    }
+}
+
+fn glob(pattern: &[u8], name: &[u8]) -> bool
+{
+    match (pattern.split_first(), name.split_first()) {
+        (None, None) => true,
+        (Some((b'*', rest)), _) => glob(rest, name) || (!name.is_empty() && glob(pattern, &name[1..])),
+        (Some((p, rest)), Some((n, name_rest))) if *p == b'?' || p == n => glob(rest, name_rest),
+        _ => false,
+    }
 }
 
 impl TryFrom<[u8; 4]> for ChunkType {
     type Error = Error;
     fn try_from(bytes : [u8; 4]) -> Result<Self> 
     {   
+        if !bytes.iter().all(|b| b.is_ascii_alphabetic())
+        {
+            return Err(PngError::InvalidChunkType{ bytes : bytes.to_vec(), offset : None });
+        }
         let mut chunk =  ChunkType{sum : 0};   
         for i in bytes
         {   
@@ -80,15 +101,16 @@ impl FromStr for ChunkType {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self>
     {   
+        let bytes = s.as_bytes();
+        if bytes.len() != 4 || !bytes.iter().all(|b| b.is_ascii_alphabetic())
+        {
+            return Err(PngError::InvalidChunkType{ bytes : bytes.to_vec(), offset : None });
+        }
         let mut chunk =  ChunkType{sum : 0};  
-        for c in s.chars()
+        for c in bytes
         {
             chunk.sum <<= 8;
-            if !(c as u8).is_ascii_alphabetic()
-            {
-                return Err("Not Alphabetic".into()); 
-            }
-            chunk.sum += c as u32;
+            chunk.sum += *c as u32;
         }
         Ok(chunk)
     }
@@ -104,8 +126,8 @@ impl fmt::Display for ChunkType {
             u8_list[3-i]= byte ;
             sum >>= 8;
         }
-        let s = std::str::from_utf8(&u8_list[0..4]).expect("invalid utf-8 sequence");
-        write!(f, "{}", s)
+        // `sum` is public, so it may hold bytes that are not letters.
+        write!(f, "{}", String::from_utf8_lossy(&u8_list))
     }
 }
 
@@ -193,6 +215,14 @@ mod tests {
 
         let chunk = ChunkType::from_str("Ru1t");
         assert!(chunk.is_err());
+
+        let chunk = ChunkType::from_str("RuStY");
+        assert!(chunk.is_err());
+
+        for bytes in [[82, 117, 49, 116], [0xff, 0, 0, 0], [b'I', b'E', b'N', b' ']]
+        {
+            assert!(matches!(ChunkType::try_from(bytes), Err(PngError::InvalidChunkType{ .. })));
+        }
     }
 
     #[test]
@@ -208,7 +238,17 @@ mod tests {
         let _chunk_string = format!("{}", chunk_type_1);
         let _are_chunks_equal = chunk_type_1 == chunk_type_2;
     }
-}
-
-
 
+    #[test]
+    pub fn test_chunk_type_matches_pattern() {
+        let chunk = ChunkType::from_str("zTXt").unwrap();
+        for pattern in ["zTXt", "*TXt", "?TX?", "*", "z*t", "****"]
+        {
+            assert!(chunk.matches_pattern(pattern), "{}", pattern);
+        }
+        for pattern in ["ztxt", "TXt", "?TX", "z*T", "zTXt?"]
+        {
+            assert!(!chunk.matches_pattern(pattern), "{}", pattern);
+        }
+    }
+}
@@ -1,89 +1,766 @@
-use crate::args;
-use crate::png::{Png};
-use crate::chunk_type::ChunkType; 
+use crate::args::{self, Format};
+use crate::output;
+use clap::error::ErrorKind;
+use pngme::chunk::Chunk;
+use pngme::png::{ChunkPosition, ParseLimits, ParseOptions, Png};
+use pngme::reader::PngReader;
+use pngme::writer::PngWriter;
+use pngme::chunk_type::ChunkType; 
 use std::str::FromStr; 
-use crate::chunk::Chunk;
-use std::fs; 
+use pngme::atomic;
+use pngme::crypto::{PublicKey, SecretKey};
+use pngme::envelope::{self, Encryption, Envelope};
+use pngme::ops::Occurrence;
+use pngme::split::Part;
+use pngme::text::{self, TextChunk};
+use pngme::error::PngError;
+use pngme::ihdr::ImageHeader;
+use pngme::Result;
+use serde_json::{json, Value};
+use std::env;
+use std::fs::{self, File, OpenOptions}; 
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::Path;
 
 
-pub fn encode(args: &args::EncodeArgs)
+pub fn encode(args: &args::EncodeArgs, format: Format, limits: ParseLimits) -> Result<()>
 {   
     let fp = args.file_path.clone(); 
     let chunkt_str = args.chunk_type.clone();
-    let msg = args.message.clone(); 
 
-    let content = fs::read(&fp).unwrap();
+    ChunkType::from_str(&chunkt_str)?; 
+    if args.stdin && fp == "-"
+    {
+        output::usage_error(format, ErrorKind::ArgumentConflict, "the png and the payload cannot both come from stdin");
+    }
+    let msg = match (&args.message, &args.input_file) {
+        (Some(message), _) => message.as_bytes().to_vec(),
+        (None, Some(path)) => fs::read(path)?,
+        (None, None) => {
+            let mut buf = Vec::new();
+            io::stdin().lock().read_to_end(&mut buf)?;
+            buf
+        }
+    };
+    let message_length = msg.len();
+    let msg = if args.raw
+    {
+        msg
+    }
+    else
+    {
+        let encryption = if args.encrypt
+        {
+            Encryption::Passphrase(passphrase(true, format)?)
+        }
+        else if !args.recipient.is_empty()
+        {
+            Encryption::Recipients(args.recipient.iter().map(|r| read_public_key(r)).collect::<Result<_>>()?)
+        }
+        else
+        {
+            Encryption::None
+        };
+        let default_type = if args.message.is_some() { envelope::TEXT_CONTENT_TYPE } else { envelope::BINARY_CONTENT_TYPE };
+        let content_type = args.content_type.as_deref().unwrap_or(default_type);
+        Envelope::seal(&msg, content_type, args.compress, &encryption)?.as_bytes()
+    };
 
-    let mut png = Png::try_from(&content[..]).unwrap(); 
+    let position = match (args.index, args.position) {
+        (Some(i), _) => ChunkPosition::Index(i),
+        (None, args::Placement::BeforeIend) => ChunkPosition::BeforeIend,
+        (None, args::Placement::AfterIhdr) => ChunkPosition::AfterIhdr,
+        (None, args::Placement::BeforeIdat) => ChunkPosition::BeforeIdat,
+    };
+    if args.split_size == Some(0) || args.parts == Some(0)
+    {
+        output::usage_error(format, ErrorKind::InvalidValue, "a message cannot be split into parts of zero bytes or into zero parts");
+    }
+    let part_size = match (args.split_size, args.parts) {
+        (Some(size), _) => Some(size),
+        (None, Some(parts)) => Some(msg.len().div_ceil(parts).max(1)),
+        (None, None) => None,
+    };
+    rewrite(&fp, args.output.as_deref(), args.backup, format, limits, |reader, writer| {
+        match part_size {
+            Some(size) => pngme::encode_split_stream(reader, writer, &chunkt_str, &msg, size, position),
+            None => pngme::encode_stream(reader, writer, &chunkt_str, &msg, position),
+        }
+    })?;
+    if format == Format::Json
+    {
+        output::success(json!({
+            "output" : args.output.as_deref().unwrap_or(&fp),
+            "chunk_type" : chunkt_str,
+            "message_length" : message_length,
+            "chunks" : part_size.map_or(1, |size| msg.len().div_ceil(size).max(1)),
+        }))?;
+    }
+    Ok(())
+}
+
+pub fn decode(args: &args::DecodeArgs, format: Format, limits: ParseLimits) -> Result<()>
+{
+    let fp = args.file_path.clone(); 
+    let chunkt_str = args.chunk_type.clone();
+    if let Some(chunkt_str) = &chunkt_str
+    {
+        ChunkType::from_str(chunkt_str)?; 
+    }
+    if args.raw && format == Format::Json
+    {
+        output::usage_error(format, ErrorKind::ArgumentConflict, "--raw writes the message bytes to stdout and cannot be used with --format json");
+    }
 
-    let chunkt = ChunkType::from_str(&chunkt_str).unwrap(); 
-    let msg_b = msg.as_bytes().to_vec(); 
-    let chunk = Chunk::new(chunkt, msg_b);
+    let options = ParseOptions{ lenient : args.lenient, limits };
+    let mut reader = PngReader::with_options(open_input(&fp)?, options)?
+        .skip_idat(chunkt_str.as_deref() != Some("IDAT")); 
 
-    png.append_chunk(chunk);
+    let occurrence = occurrence(args.all, args.index);
+    let msgs = match &chunkt_str {
+        Some(chunkt_str) => pngme::find_messages(&mut reader, chunkt_str, occurrence),
+        None => pngme::find_envelopes(&mut reader, occurrence)
+            .map(|found| found.iter().map(Envelope::as_bytes).collect()),
+    };
+    warn_crc_errors(&reader, format);
+    if matches!(msgs, Err(PngError::MissingChunk{ .. })) && format == Format::Text
+    {
+        hint_trailing_chunks(&mut reader, chunkt_str.as_deref(), &fp);
+    }
 
-    let final_content = png.as_bytes(); 
+    let mut keys = Keys{ identity_path : args.identity.as_deref(), max_size : limits.max_decompressed_size, format, passphrase : None, identity : None };
+    let msgs = msgs?
+        .iter()
+        .map(|msg| keys.open(msg))
+        .collect::<Result<Vec<_>>>()?;
 
-    fs::write(&fp, final_content).unwrap(); 
+    if let Some(path) = &args.output_file
+    {
+        atomic::write_file(Path::new(path), |out| Ok(out.write_all(&msgs[0])?))?;
+    }
+    else if args.raw
+    {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(&msgs[0])?;
+        stdout.flush()?;
+    }
+    else if format == Format::Text
+    {
+        let mut stdout = io::stdout().lock();
+        for msg in &msgs
+        {
+            writeln!(stdout, "The hidden message is {:?}", String::from_utf8_lossy(msg))?;
+        }
+    }
+    if format == Format::Json
+    {
+        output::success(json!({
+            "messages" : msgs.iter().map(|msg| output::message(msg)).collect::<Vec<_>>(),
+            "output_file" : args.output_file,
+            "crc_errors" : crc_errors(&reader),
+        }))?;
+    }
+    Ok(())
 }
 
-pub fn decode(args: &args::DecodeArgs)
+pub fn remove(args: &args::RemoveArgs, format: Format, limits: ParseLimits) -> Result<()>
 {
     let fp = args.file_path.clone(); 
     let chunkt_str = args.chunk_type.clone();
-    let chunkt = ChunkType::from_str(&chunkt_str).unwrap(); 
+    ChunkType::from_str(&chunkt_str)?; 
+
+    let mut removed = 0;
+    rewrite(&fp, args.output.as_deref(), args.backup, format, limits, |reader, writer| {
+        let result = pngme::remove_stream(reader, writer, &chunkt_str, occurrence(args.all, args.index));
+        if matches!(result, Err(PngError::MissingChunk{ .. })) && format == Format::Text
+        {
+            hint_trailing_chunks(reader, Some(&chunkt_str), &fp);
+        }
+        removed = result?.len();
+        Ok(())
+    })?;
+    if format == Format::Json
+    {
+        output::success(json!({
+            "output" : args.output.as_deref().unwrap_or(&fp),
+            "chunk_type" : chunkt_str,
+            "removed" : removed,
+        }))?;
+    }
+    Ok(())
+}
 
-    let content = fs::read(fp).unwrap();
-    let png = Png::try_from(&content[..]).unwrap(); 
+pub fn print(args: &args::PrintArgs, format: Format, limits: ParseLimits) -> Result<()>
+{
+    let fp = args.file_path.clone(); 
 
-    let chunkl = png.chunks(); 
+    // Always lenient: a bad CRC shows up in its row instead of ending the listing.
+    let options = ParseOptions{ lenient : true, limits };
+    let mut reader = PngReader::with_options(open_input(&fp)?, options)?; 
 
-    for chunk in chunkl.iter()
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    if format == Format::Text
+    {
+        writeln!(out, "{:>5}  {:>10}  {:4}  {:>10}  {:3}  {:5}  PREVIEW", "INDEX", "OFFSET", "TYPE", "LENGTH", "CRC", "PROPS")?;
+    }
+    let mut listed = Vec::new();
+    let mut offset = Png::STANDARD_HEADER.len();
+    for (index, chunk) in reader.by_ref().enumerate()
     {
-        if chunk.chunk_type == chunkt
-        {   
-            println!("The hidden message is {:?}", chunk.data_as_string().unwrap());
-            return; 
+        let chunk = chunk?;
+        let chunk_offset = offset;
+        offset += 12 + chunk.data().len();
+
+        let chunk_type = chunk.chunk_type();
+        if args.type_pattern.as_ref().is_some_and(|pattern| !chunk_type.matches_pattern(pattern))
+            || !args.property.iter().all(|property| has_property(chunk_type, *property))
+        {
+            continue;
+        }
+        match format {
+            Format::Text => writeln!(out, "{:>5}  {:>10}  {:4}  {:>10}  {:3}  {:5}  {}",
+                                     index, chunk_offset, chunk_type.to_string(), chunk.length(),
+                                     if chunk.crc_is_valid() { "ok" } else { "BAD" },
+                                     properties(chunk_type), preview(&chunk, &limits))?,
+            Format::Json => listed.push(json!({
+                "index" : index,
+                "offset" : chunk_offset,
+                "type" : chunk_type.to_string(),
+                "length" : chunk.length(),
+                "crc" : chunk.crc(),
+                "crc_valid" : chunk.crc_is_valid(),
+                "critical" : chunk_type.is_critical(),
+                "public" : chunk_type.is_public(),
+                "reserved_bit_valid" : chunk_type.is_reserved_bit_valid(),
+                "safe_to_copy" : chunk_type.is_safe_to_copy(),
+                "preview" : preview(&chunk, &limits),
+            })),
+        }
+    }
+    let trailing_offset = reader.offset();
+    let trailing = reader.read_trailing_data()?;
+    match format {
+        Format::Text if !trailing.is_empty() => {
+            writeln!(out, "{} bytes after IEND at offset {}: {}", trailing.len(), trailing_offset, preview_data(&trailing))?;
         }
+        Format::Text => {}
+        Format::Json => output::success(json!({
+            "chunks" : listed,
+            "trailing_data" : { "offset" : trailing_offset, "length" : trailing.len() },
+            "crc_errors" : crc_errors(&reader),
+        }))?,
     }
+    Ok(())
+}
 
-    println!("Hidden message not found");
+/// Print the CRC mismatches a lenient read let through, in text mode. JSON
+/// output carries them in the result instead.
+fn warn_crc_errors<R: Read>(reader: &PngReader<R>, format: Format)
+{
+    if format == Format::Text
+    {
+        for mismatch in reader.crc_errors()
+        {
+            eprintln!("Warning: {}", mismatch);
+        }
+    }
 }
 
-pub fn remove(args: &args::RemoveArgs)
+/// Point at `trailing restore` when a chunk of type `chunk_type`, or of any
+/// type, is stuck after IEND where earlier releases put messages.
+fn hint_trailing_chunks<R: Read>(reader: &mut PngReader<R>, chunk_type: Option<&str>, fp: &str)
 {
-    let fp = args.file_path.clone(); 
-    let chunkt_str = args.chunk_type.clone();
-    let chunkt = ChunkType::from_str(&chunkt_str).unwrap(); 
+    let Ok(trailing) = reader.read_trailing_data() else { return };
+    let stuck = pngme::trailing_chunks(&trailing).iter()
+        .any(|chunk| chunk_type.is_none_or(|chunk_type| chunk.chunk_type().to_string() == chunk_type));
+    if stuck
+    {
+        eprintln!("Hint: there are chunks after IEND, where earlier pngme releases put messages; `pngme trailing restore {}` moves them back", fp);
+    }
+}
 
-    let content = fs::read(&fp).unwrap();
-    let mut png = Png::try_from(&content[..]).unwrap(); 
+fn warn_skipped_texts(skipped: &[text::SkippedText])
+{
+    for skipped in skipped
+    {
+        eprintln!("Warning: skipping the text chunk at offset {}: {}", skipped.offset, skipped.error);
+    }
+}
 
-    png.remove_chunk(&String::from_utf8(chunkt.bytes().to_vec()).unwrap()).unwrap();
+fn texts_result(found: &text::FoundTexts) -> Value
+{
+    json!({
+        "texts" : found.texts.iter().map(output::text_chunk).collect::<Vec<_>>(),
+        "skipped" : found.skipped.iter().map(output::skipped_text).collect::<Vec<_>>(),
+    })
+}
 
-    let final_content = png.as_bytes(); 
+fn crc_errors<R: Read>(reader: &PngReader<R>) -> Vec<Value>
+{
+    reader.crc_errors().iter().map(output::crc_mismatch).collect()
+}
 
-    fs::write(&fp, final_content).unwrap(); 
+fn has_property(chunk_type: &ChunkType, property: args::Property) -> bool
+{
+    match property {
+        args::Property::Critical => chunk_type.is_critical(),
+        args::Property::Ancillary => !chunk_type.is_critical(),
+        args::Property::Public => chunk_type.is_public(),
+        args::Property::Private => !chunk_type.is_public(),
+        args::Property::Reserved => !chunk_type.is_reserved_bit_valid(),
+        args::Property::SafeToCopy => chunk_type.is_safe_to_copy(),
+        args::Property::UnsafeToCopy => !chunk_type.is_safe_to_copy(),
+    }
 }
 
-pub fn print(args: &args::PrintArgs)
+/// The PROPS column: C/a critical or ancillary, P/p public or private,
+/// R if the reserved bit is set, S/u safe or unsafe to copy.
+fn properties(chunk_type: &ChunkType) -> String
 {
-    let fp = args.file_path.clone(); 
-    let chunkt = ChunkType::from_str("dEAD").unwrap(); 
+    [
+        if chunk_type.is_critical() { 'C' } else { 'a' },
+        if chunk_type.is_public() { 'P' } else { 'p' },
+        if chunk_type.is_reserved_bit_valid() { '-' } else { 'R' },
+        if chunk_type.is_safe_to_copy() { 'S' } else { 'u' },
+    ].iter().collect()
+}
+
+/// A one-line summary of a chunk's data for the print table.
+fn preview(chunk: &Chunk, limits: &ParseLimits) -> String
+{
+    const WIDTH : usize = 40;
+    let data = chunk.data();
+    if let Ok(header) = ImageHeader::try_from(chunk)
+    {
+        return format!("{}x{}, {}-bit {}{}", header.width, header.height, header.bit_depth, header.color_type,
+                       if header.interlaced { ", interlaced" } else { "" })
+    }
+    if TextChunk::is_text_type(chunk.chunk_type())
+    {
+        return match TextChunk::parse(chunk, limits.max_decompressed_size) {
+            Ok(text) => truncate(&format!("{}: {:?}", text.keyword(), text.text()), WIDTH),
+            Err(err) => format!("({})", err),
+        }
+    }
+    if let Ok(envelope) = Envelope::try_from(&data[..])
+    {
+        let mut flags = Vec::new();
+        if envelope.is_compressed() { flags.push("compressed"); }
+        if envelope.needs_passphrase() { flags.push("passphrase"); }
+        if envelope.needs_identity() { flags.push("recipients"); }
+        let mut summary = format!("pngme message, {}, {} bytes", envelope.content_type, envelope.original_len);
+        for flag in flags
+        {
+            summary.push_str(", ");
+            summary.push_str(flag);
+        }
+        return summary
+    }
+    if let Ok(part) = Part::try_from(&data[..])
+    {
+        return format!("pngme part {} of {} of message {}", part.sequence, part.total, part.id_hex())
+    }
+    preview_data(data)
+}
+
+/// A one-line summary of bytes with no known structure: quoted when they are
+/// printable ASCII, else the first few in hex.
+fn preview_data(data: &[u8]) -> String
+{
+    const WIDTH : usize = 40;
+    if data.is_empty()
+    {
+        return String::new()
+    }
+    if data.iter().all(|b| b.is_ascii_graphic() || *b == b' ')
+    {
+        return truncate(&format!("{:?}", String::from_utf8_lossy(data)), WIDTH)
+    }
+    let shown = data.len().min(WIDTH / 3);
+    let hex: Vec<String> = data[..shown].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}{}", hex.join(" "), if shown < data.len() { " ..." } else { "" })
+}
+
+fn truncate(text: &str, width: usize) -> String
+{
+    if text.chars().count() <= width
+    {
+        return text.to_string()
+    }
+    let mut short: String = text.chars().take(width - 3).collect();
+    short.push_str("...");
+    short
+}
+
+/// Keys for opening messages, asked for or loaded only once a message needs them.
+struct Keys<'a>{
+    identity_path : Option<&'a str>,
+    max_size : u64,
+    format : Format,
+    passphrase : Option<String>,
+    identity : Option<SecretKey>,
+}
+
+impl Keys<'_>{
+    /// The payload of `msg`: opened if it is a pngme envelope, and as it is
+    /// otherwise.
+    fn open(&mut self, msg: &[u8]) -> Result<Vec<u8>>
+    {
+        if Envelope::is_envelope(msg)
+        {
+            let envelope = Envelope::try_from(msg)?;
+            let max_size = self.max_size;
+            let pass = if envelope.needs_passphrase() { Some(self.passphrase()?.to_string()) } else { None };
+            let identity = if envelope.needs_identity() { Some(self.identity()?) } else { None };
+            return envelope.open_with_limit(pass.as_deref(), identity, max_size)
+        }
+        Ok(msg.to_vec())
+    }
+
+    fn passphrase(&mut self) -> Result<&str>
+    {
+        if self.passphrase.is_none()
+        {
+            self.passphrase = Some(passphrase(false, self.format)?);
+        }
+        Ok(self.passphrase.as_deref().unwrap())
+    }
+
+    fn identity(&mut self) -> Result<&SecretKey>
+    {
+        if self.identity.is_none()
+        {
+            let path = self.identity_path.ok_or_else(|| PngError::InvalidKey{
+                reason : "the message is encrypted to recipients, pass --identity".to_string()
+            })?;
+            self.identity = Some(SecretKey::from_str(&fs::read_to_string(path)?)?);
+        }
+        Ok(self.identity.as_ref().unwrap())
+    }
+}
 
-    let content = fs::read(fp).unwrap();
-    let png = Png::try_from(&content[..]).unwrap(); 
+fn occurrence(all: bool, index: Option<usize>) -> Occurrence
+{
+    match (all, index) {
+        (true, _) => Occurrence::All,
+        (false, Some(i)) => Occurrence::Nth(i),
+        (false, None) => Occurrence::First,
+    }
+}
 
-    let chunkl = png.chunks(); 
+pub fn info(args: &args::InfoArgs, format: Format, limits: ParseLimits) -> Result<()>
+{
+    let mut reader = read_input(&args.file_path, limits)?.skip_idat(true);
+    let ihdr = reader.find(|chunk| chunk.as_ref().map_or(true, |chunk| &chunk.chunk_type().bytes() == b"IHDR"))
+        .ok_or_else(|| PngError::MissingChunk{ chunk_type : "IHDR".to_string() })??;
+    let header = ImageHeader::try_from(&ihdr)?;
+    match format {
+        Format::Text => writeln!(io::stdout().lock(), "{}", header)?,
+        Format::Json => output::success(output::image_header(&header))?,
+    }
+    Ok(())
+}
 
-    println!("The potential hidden messages are :\n"); 
+pub fn keygen(args: &args::KeygenArgs, format: Format) -> Result<()>
+{
+    let secret = SecretKey::generate();
 
-    for chunk in chunkl.iter()
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
     {
-        if chunk.chunk_type == chunkt
-        {   
-            println!("{:?}\n", chunk.data_as_string().unwrap());
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(&args.output)?;
+    writeln!(file, "{}", secret)?;
+    file.sync_all()?;
+
+    match format {
+        Format::Text => writeln!(io::stdout().lock(), "{}", secret.public_key())?,
+        Format::Json => output::success(json!({
+            "public_key" : secret.public_key().to_string(),
+            "secret_key_file" : args.output,
+        }))?,
+    }
+    Ok(())
+}
+
+pub fn text(args: &args::TextArgs, format: Format, limits: ParseLimits) -> Result<()>
+{
+    match &args.command {
+        args::TextCommands::List(args) => {
+            let mut reader = read_input(&args.file_path, limits)?.skip_idat(true);
+            let found = text::find_texts(&mut reader, None)?;
+            match format {
+                Format::Text => {
+                    warn_skipped_texts(&found.skipped);
+                    let mut stdout = io::stdout().lock();
+                    for text in &found.texts
+                    {
+                        writeln!(stdout, "{}", text)?;
+                    }
+                }
+                Format::Json => output::success(texts_result(&found))?,
+            }
+            Ok(())
+        }
+        args::TextCommands::Get(args) => {
+            let mut reader = read_input(&args.file_path, limits)?.skip_idat(true);
+            let mut found = text::find_texts(&mut reader, Some(&args.keyword))?;
+            if found.texts.is_empty()
+            {
+                // Only broken chunks have the keyword; say what is wrong with them.
+                return Err(match found.skipped.pop() {
+                    Some(skipped) => skipped.error,
+                    None => text::missing_keyword(&args.keyword),
+                })
+            }
+            match format {
+                Format::Text => {
+                    warn_skipped_texts(&found.skipped);
+                    let mut stdout = io::stdout().lock();
+                    for text in &found.texts
+                    {
+                        writeln!(stdout, "{}", text.text())?;
+                    }
+                }
+                Format::Json => output::success(texts_result(&found))?,
+            }
+            Ok(())
+        }
+        args::TextCommands::Set(args) => {
+            let keyword = args.keyword.clone();
+            let latin1 = args.text.chars().all(|c| (c as u32) < 256);
+            let international = args.international || args.language.is_some() || args.translated_keyword.is_some() || !latin1;
+            let chunk = if international
+            {
+                TextChunk::International{
+                    keyword,
+                    compressed : args.compress,
+                    language : args.language.clone().unwrap_or_default(),
+                    translated_keyword : args.translated_keyword.clone().unwrap_or_default(),
+                    text : args.text.clone(),
+                }
+            }
+            else if args.compress
+            {
+                TextChunk::Compressed{ keyword, text : args.text.clone() }
+            }
+            else
+            {
+                TextChunk::Text{ keyword, text : args.text.clone() }
+            };
+            chunk.to_chunk()?;
+            rewrite(&args.file_path, args.output.as_deref(), args.backup, format, limits, |reader, writer| {
+                text::set_text_stream(reader, writer, &chunk)
+            })?;
+            if format == Format::Json
+            {
+                output::success(json!({
+                    "output" : args.output.as_deref().unwrap_or(&args.file_path),
+                    "text" : output::text_chunk(&chunk),
+                }))?;
+            }
+            Ok(())
+        }
+        args::TextCommands::Delete(args) => {
+            let mut removed = text::FoundTexts::default();
+            rewrite(&args.file_path, args.output.as_deref(), args.backup, format, limits, |reader, writer| {
+                removed = text::delete_text_stream(reader, writer, &args.keyword)?;
+                Ok(())
+            })?;
+            match format {
+                Format::Text => {
+                    for skipped in &removed.skipped
+                    {
+                        eprintln!("Warning: deleted the text chunk at offset {} without reading it: {}", skipped.offset, skipped.error);
+                    }
+                }
+                Format::Json => output::success(json!({
+                    "output" : args.output.as_deref().unwrap_or(&args.file_path),
+                    "removed" : removed.texts.iter().map(output::text_chunk).collect::<Vec<_>>(),
+                    "skipped" : removed.skipped.iter().map(output::skipped_text).collect::<Vec<_>>(),
+                }))?,
+            }
+            Ok(())
         }
     }
-    println!("End\n"); 
-}
\ No newline at end of file
+}
+
+pub fn trailing(args: &args::TrailingArgs, format: Format, limits: ParseLimits) -> Result<()>
+{
+    match &args.command {
+        args::TrailingCommands::Show(args) => {
+            let mut reader = read_input(&args.file_path, limits)?.skip_idat(true);
+            let (offset, trailing) = read_trailing(&mut reader)?;
+            match format {
+                Format::Text if trailing.is_empty() => writeln!(io::stdout().lock(), "No data after IEND")?,
+                Format::Text => writeln!(io::stdout().lock(), "{} bytes after IEND at offset {}: {}", trailing.len(), offset, preview_data(&trailing))?,
+                Format::Json => output::success(json!({
+                    "offset" : offset,
+                    "length" : trailing.len(),
+                    "preview" : preview_data(&trailing),
+                }))?,
+            }
+            Ok(())
+        }
+        args::TrailingCommands::Extract(args) => {
+            if args.output.is_none() && format == Format::Json
+            {
+                output::usage_error(format, ErrorKind::MissingRequiredArgument, "the data cannot go to stdout with --format json, pass --output");
+            }
+            let mut reader = read_input(&args.file_path, limits)?.skip_idat(true);
+            let (offset, trailing) = read_trailing(&mut reader)?;
+            match &args.output {
+                Some(path) => atomic::write_file(Path::new(path), |out| Ok(out.write_all(&trailing)?))?,
+                None => {
+                    let mut stdout = io::stdout().lock();
+                    stdout.write_all(&trailing)?;
+                    stdout.flush()?;
+                }
+            }
+            if format == Format::Json
+            {
+                output::success(json!({ "offset" : offset, "length" : trailing.len(), "output" : args.output }))?;
+            }
+            Ok(())
+        }
+        args::TrailingCommands::Strip(args) => {
+            let mut removed = 0;
+            rewrite(&args.file_path, args.output.as_deref(), args.backup, format, limits, |reader, writer| {
+                for chunk in reader.by_ref()
+                {
+                    writer.write_chunk(&chunk?)?;
+                }
+                removed = reader.read_trailing_data()?.len();
+                Ok(())
+            })?;
+            if format == Format::Json
+            {
+                output::success(json!({
+                    "output" : args.output.as_deref().unwrap_or(&args.file_path),
+                    "removed" : removed,
+                }))?;
+            }
+            Ok(())
+        }
+        args::TrailingCommands::Restore(args) => {
+            let mut restored = Vec::new();
+            rewrite(&args.file_path, args.output.as_deref(), args.backup, format, limits, |reader, writer| {
+                restored = pngme::restore_trailing_stream(reader, writer)?;
+                Ok(())
+            })?;
+            if format == Format::Json
+            {
+                output::success(json!({
+                    "output" : args.output.as_deref().unwrap_or(&args.file_path),
+                    "restored" : restored.iter().map(|chunk| chunk.chunk_type().to_string()).collect::<Vec<_>>(),
+                }))?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// The data after IEND and its offset, failing when the file has no IEND.
+fn read_trailing<R: Read>(reader: &mut PngReader<R>) -> Result<(usize, Vec<u8>)>
+{
+    for chunk in reader.by_ref()
+    {
+        if &chunk?.chunk_type().bytes() == b"IEND"
+        {
+            let offset = reader.offset();
+            return Ok((offset, reader.read_trailing_data()?))
+        }
+    }
+    Err(PngError::MissingChunk{ chunk_type : "IEND".to_string() })
+}
+
+/// A public key given inline, or read from the file it names.
+fn read_public_key(key: &str) -> Result<PublicKey>
+{
+    if Path::new(key).is_file()
+    {
+        return PublicKey::from_str(&fs::read_to_string(key)?)
+    }
+    PublicKey::from_str(key)
+}
+
+/// The passphrase from $PNGME_PASSPHRASE, or else prompted for on the terminal.
+fn passphrase(confirm: bool, format: Format) -> Result<String>
+{
+    let pass = match env::var("PNGME_PASSPHRASE") {
+        Ok(pass) => pass,
+        Err(_) => {
+            let pass = rpassword::prompt_password("Passphrase: ")?;
+            if confirm && rpassword::prompt_password("Confirm passphrase: ")? != pass
+            {
+                output::usage_error(format, ErrorKind::ValueValidation, "the passphrases do not match");
+            }
+            pass
+        }
+    };
+    if pass.is_empty()
+    {
+        output::usage_error(format, ErrorKind::ValueValidation, "the passphrase cannot be empty");
+    }
+    Ok(pass)
+}
+
+/// Open a png for reading, with "-" meaning stdin.
+fn open_input(fp: &str) -> Result<Box<dyn BufRead>>
+{
+    if fp == "-"
+    {
+        return Ok(Box::new(io::stdin().lock()))
+    }
+    Ok(Box::new(BufReader::new(File::open(fp)?)))
+}
+
+/// A reader over the png at `fp` that rejects bad CRCs and enforces `limits`.
+fn read_input(fp: &str, limits: ParseLimits) -> Result<PngReader<Box<dyn BufRead>>>
+{
+    PngReader::with_options(open_input(fp)?, ParseOptions{ lenient : false, limits })
+}
+
+/// Stream the png at `fp` through `edit` into `output`. Data after IEND is
+/// copied over unless `edit` reads it first.
+///
+/// Without an output the input file is replaced atomically, or the result goes
+/// to stdout when the input is stdin. An output of "-" also means stdout, which
+/// JSON output needs for itself. Any other output is written through a
+/// temporary file too, so an output naming the input file is safe. Output to
+/// stdout is held in memory until `edit` succeeds, so a failed edit writes
+/// nothing there either.
+fn rewrite<F>(fp: &str, output: Option<&str>, backup: bool, format: Format, limits: ParseLimits, edit: F) -> Result<()>
+where
+    F: FnOnce(&mut PngReader<Box<dyn BufRead>>, &mut PngWriter<&mut dyn Write>) -> Result<()>,
+{
+    let to_stdout = output == Some("-") || (output.is_none() && fp == "-");
+    if to_stdout && format == Format::Json
+    {
+        output::usage_error(format, ErrorKind::ArgumentConflict, "the png cannot go to stdout with --format json, pass --output");
+    }
+    let mut reader = read_input(fp, limits)?;
+    let run = |out: &mut dyn Write| -> Result<()> {
+        let mut writer = PngWriter::new(out)?;
+        edit(&mut reader, &mut writer)?;
+        writer.write_trailing_data(&reader.read_trailing_data()?)?;
+        writer.finish()?;
+        Ok(())
+    };
+
+    match output {
+        None if fp != "-" => atomic::replace_file(Path::new(fp), backup, |out| run(out)),
+        None | Some("-") => {
+            let mut out = Vec::new();
+            run(&mut out)?;
+            let mut stdout = io::stdout().lock();
+            stdout.write_all(&out)?;
+            Ok(stdout.flush()?)
+        }
+        Some(path) => atomic::write_file(Path::new(path), |out| run(out)),
+    }
+}
@@ -0,0 +1,387 @@
+use std::fmt;
+use std::str::FromStr;
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Nonce, XChaCha20Poly1305, XNonce};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use x25519_dalek::{EphemeralSecret, StaticSecret};
+
+use crate::Result;
+use crate::error::PngError;
+
+/// Marks a payload sealed by `seal_with_passphrase`.
+pub const PASSPHRASE_MAGIC : [u8; 4] = *b"PMpw";
+const PASSPHRASE_VERSION : u8 = 1;
+
+const SALT_LEN : usize = 16;
+const NONCE_LEN : usize = 24;
+const KEY_LEN : usize = 32;
+/// Refuse KDF parameters past these when opening, so a crafted file cannot
+/// make one attempt take more than 256 MiB or spin for minutes. The default
+/// memory cost is 19 MiB.
+const MAX_M_COST : u32 = 1 << 18;
+const MAX_T_COST : u32 = 16;
+const MAX_P_COST : u32 = 16;
+/// magic, version, three u32 KDF parameters, salt and nonce.
+const HEADER_LEN : usize = 4 + 1 + 12 + SALT_LEN + NONCE_LEN;
+
+/// Argon2id cost parameters, stored in the sealed payload so they can change later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfParams{
+    /// Memory in KiB.
+    pub m_cost : u32,
+    pub t_cost : u32,
+    pub p_cost : u32,
+}
+
+impl Default for KdfParams{
+    fn default() -> Self{
+        KdfParams{
+            m_cost : Params::DEFAULT_M_COST,
+            t_cost : Params::DEFAULT_T_COST,
+            p_cost : Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+/// Encrypt `plaintext` with a key derived from `passphrase`.
+///
+/// The key comes from Argon2id over a random salt and the payload is sealed
+/// with XChaCha20-Poly1305. The header (magic, version, KDF parameters, salt
+/// and nonce) is stored in front of the ciphertext and authenticated with it,
+/// along with `aad`, which is not stored and has to be passed again to open it.
+pub fn seal_with_passphrase(plaintext: &[u8], passphrase: &str, aad: &[u8]) -> Result<Vec<u8>>
+{
+    seal_with_params(plaintext, passphrase, KdfParams::default(), aad)
+}
+
+pub fn seal_with_params(plaintext: &[u8], passphrase: &str, params: KdfParams, aad: &[u8]) -> Result<Vec<u8>>
+{
+    let mut salt = [0; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+
+    let mut sealed = Vec::with_capacity(HEADER_LEN + plaintext.len() + 16);
+    sealed.extend_from_slice(&PASSPHRASE_MAGIC);
+    sealed.push(PASSPHRASE_VERSION);
+    sealed.extend_from_slice(&params.m_cost.to_be_bytes());
+    sealed.extend_from_slice(&params.t_cost.to_be_bytes());
+    sealed.extend_from_slice(&params.p_cost.to_be_bytes());
+    sealed.extend_from_slice(&salt);
+    sealed.extend_from_slice(&nonce);
+
+    let cipher = XChaCha20Poly1305::new(&derive_key(passphrase, &salt, params)?.into());
+    let ciphertext = cipher.encrypt(&nonce, Payload{ msg : plaintext, aad : &[&sealed[..], aad].concat() })
+        .map_err(|_| PngError::MalformedPayload{ reason : "encryption failed".to_string() })?;
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+/// Reverse `seal_with_passphrase`, given the same `aad`. A wrong passphrase
+/// and tampered data both give `PngError::DecryptionFailed`.
+pub fn open_with_passphrase(sealed: &[u8], passphrase: &str, aad: &[u8]) -> Result<Vec<u8>>
+{
+    if !is_passphrase_sealed(sealed)
+    {
+        return Err(PngError::MalformedPayload{ reason : "not a passphrase-sealed payload".to_string() })
+    }
+    if sealed.len() < HEADER_LEN + 16
+    {
+        return Err(PngError::MalformedPayload{ reason : "sealed payload is truncated".to_string() })
+    }
+    if sealed[4] != PASSPHRASE_VERSION
+    {
+        return Err(PngError::MalformedPayload{ reason : format!("unknown encryption version {}", sealed[4]) })
+    }
+
+    let (header, ciphertext) = sealed.split_at(HEADER_LEN);
+    let u32_at = |i: usize| u32::from_be_bytes(header[i..i + 4].try_into().unwrap());
+    let params = KdfParams{ m_cost : u32_at(5), t_cost : u32_at(9), p_cost : u32_at(13) };
+    if params.m_cost > MAX_M_COST || params.t_cost > MAX_T_COST || params.p_cost > MAX_P_COST
+    {
+        return Err(PngError::MalformedPayload{ reason : "key derivation parameters too expensive".to_string() })
+    }
+    let salt = &header[17..17 + SALT_LEN];
+    let nonce = XNonce::from_slice(&header[17 + SALT_LEN..]);
+
+    let cipher = XChaCha20Poly1305::new(&derive_key(passphrase, salt, params)?.into());
+    cipher.decrypt(nonce, Payload{ msg : ciphertext, aad : &[header, aad].concat() })
+        .map_err(|_| PngError::DecryptionFailed)
+}
+
+/// Whether `data` starts like a payload from `seal_with_passphrase`.
+pub fn is_passphrase_sealed(data: &[u8]) -> bool
+{
+    data.starts_with(&PASSPHRASE_MAGIC)
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], params: KdfParams) -> Result<[u8; KEY_LEN]>
+{
+    let invalid = |err: argon2::Error| PngError::MalformedPayload{ reason : format!("bad key derivation parameters: {}", err) };
+    let params = Params::new(params.m_cost, params.t_cost, params.p_cost, Some(KEY_LEN)).map_err(invalid)?;
+    let mut key = [0; KEY_LEN];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(invalid)?;
+    Ok(key)
+}
+
+/// Marks a payload sealed by `seal_to_recipients`.
+pub const RECIPIENT_MAGIC : [u8; 4] = *b"PMpk";
+const RECIPIENT_VERSION : u8 = 1;
+const PUBLIC_KEY_PREFIX : &str = "pngme-pub:";
+const SECRET_KEY_PREFIX : &str = "pngme-sec:";
+const WRAP_INFO : &[u8] = b"pngme x25519 v1";
+/// Ephemeral public key and the wrapped file key with its tag.
+const STANZA_LEN : usize = 32 + KEY_LEN + 16;
+
+/// An X25519 public key that messages can be encrypted to.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct PublicKey(x25519_dalek::PublicKey);
+
+/// The X25519 secret key that opens messages sent to its `PublicKey`.
+pub struct SecretKey(StaticSecret);
+
+impl SecretKey{
+    pub fn generate() -> SecretKey{
+        SecretKey(StaticSecret::random_from_rng(OsRng))
+    }
+
+    pub fn public_key(&self) -> PublicKey{
+        PublicKey(x25519_dalek::PublicKey::from(&self.0))
+    }
+}
+
+impl fmt::Debug for PublicKey{
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result{
+        write!(f, "PublicKey({})", self)
+    }
+}
+
+impl fmt::Display for PublicKey{
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result{
+        write!(f, "{}{}", PUBLIC_KEY_PREFIX, hex::encode(self.0.as_bytes()))
+    }
+}
+
+/// Written out in full so it can be saved to a key file.
+impl fmt::Display for SecretKey{
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result{
+        write!(f, "{}{}", SECRET_KEY_PREFIX, hex::encode(self.0.as_bytes()))
+    }
+}
+
+impl FromStr for PublicKey{
+    type Err = PngError;
+    fn from_str(s: &str) -> Result<Self>{
+        Ok(PublicKey(x25519_dalek::PublicKey::from(parse_key(s, PUBLIC_KEY_PREFIX)?)))
+    }
+}
+
+impl FromStr for SecretKey{
+    type Err = PngError;
+    fn from_str(s: &str) -> Result<Self>{
+        Ok(SecretKey(StaticSecret::from(parse_key(s, SECRET_KEY_PREFIX)?)))
+    }
+}
+
+fn parse_key(s: &str, prefix: &str) -> Result<[u8; 32]>
+{
+    let invalid = |reason: &str| PngError::InvalidKey{ reason : reason.to_string() };
+    let hex_key = s.trim().strip_prefix(prefix).ok_or_else(|| invalid(&format!("expected a key starting with {:?}", prefix)))?;
+    let bytes = hex::decode(hex_key).map_err(|_| invalid("key is not valid hex"))?;
+    bytes.try_into().map_err(|_| invalid("key is not 32 bytes long"))
+}
+
+/// Encrypt `plaintext` so that any one of `recipients` can open it.
+///
+/// A random file key seals the payload with XChaCha20-Poly1305. For every
+/// recipient a stanza holds a fresh ephemeral X25519 public key and the file
+/// key wrapped under HKDF-SHA256 of the shared secret. The stanzas follow the
+/// magic, version and count in the header, which is authenticated with the
+/// body along with `aad`, as in `seal_with_passphrase`.
+pub fn seal_to_recipients(plaintext: &[u8], recipients: &[PublicKey], aad: &[u8]) -> Result<Vec<u8>>
+{
+    if recipients.is_empty() || recipients.len() > u8::MAX as usize
+    {
+        return Err(PngError::InvalidKey{ reason : "need between 1 and 255 recipients".to_string() })
+    }
+
+    let file_key = XChaCha20Poly1305::generate_key(&mut OsRng);
+    let mut sealed = Vec::new();
+    sealed.extend_from_slice(&RECIPIENT_MAGIC);
+    sealed.push(RECIPIENT_VERSION);
+    sealed.push(recipients.len() as u8);
+
+    for recipient in recipients
+    {
+        let ephemeral = EphemeralSecret::random_from_rng(OsRng);
+        let ephemeral_pub = x25519_dalek::PublicKey::from(&ephemeral);
+        let shared = ephemeral.diffie_hellman(&recipient.0);
+        let wrap_key = wrap_key(shared.as_bytes(), &ephemeral_pub, &recipient.0);
+        let wrapped = ChaCha20Poly1305::new(&wrap_key.into())
+            .encrypt(&Nonce::default(), file_key.as_slice())
+            .map_err(|_| PngError::MalformedPayload{ reason : "key wrapping failed".to_string() })?;
+        sealed.extend_from_slice(ephemeral_pub.as_bytes());
+        sealed.extend_from_slice(&wrapped);
+    }
+
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    sealed.extend_from_slice(&nonce);
+    let ciphertext = XChaCha20Poly1305::new(&file_key)
+        .encrypt(&nonce, Payload{ msg : plaintext, aad : &[&sealed[..], aad].concat() })
+        .map_err(|_| PngError::MalformedPayload{ reason : "encryption failed".to_string() })?;
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+/// Reverse `seal_to_recipients` with the secret key of one of the recipients
+/// and the same `aad`. Not being a recipient and tampered data both give
+/// `PngError::DecryptionFailed`.
+pub fn open_with_identity(sealed: &[u8], identity: &SecretKey, aad: &[u8]) -> Result<Vec<u8>>
+{
+    if !is_recipient_sealed(sealed)
+    {
+        return Err(PngError::MalformedPayload{ reason : "not a recipient-sealed payload".to_string() })
+    }
+    if sealed.len() < 6 || sealed[4] != RECIPIENT_VERSION
+    {
+        return Err(PngError::MalformedPayload{ reason : "unknown encryption version".to_string() })
+    }
+    let count = sealed[5] as usize;
+    let header_len = 6 + count * STANZA_LEN + NONCE_LEN;
+    if sealed.len() < header_len + 16
+    {
+        return Err(PngError::MalformedPayload{ reason : "sealed payload is truncated".to_string() })
+    }
+
+    let (header, ciphertext) = sealed.split_at(header_len);
+    let our_pub = x25519_dalek::PublicKey::from(&identity.0);
+    let file_key = header[6..6 + count * STANZA_LEN]
+        .chunks_exact(STANZA_LEN)
+        .find_map(|stanza| {
+            let ephemeral_pub: [u8; 32] = stanza[..32].try_into().unwrap();
+            let ephemeral_pub = x25519_dalek::PublicKey::from(ephemeral_pub);
+            let shared = identity.0.diffie_hellman(&ephemeral_pub);
+            let wrap_key = wrap_key(shared.as_bytes(), &ephemeral_pub, &our_pub);
+            ChaCha20Poly1305::new(&wrap_key.into())
+                .decrypt(&Nonce::default(), &stanza[32..])
+                .ok()
+        })
+        .ok_or(PngError::DecryptionFailed)?;
+    if file_key.len() != KEY_LEN
+    {
+        return Err(PngError::DecryptionFailed)
+    }
+
+    let nonce = XNonce::from_slice(&header[header_len - NONCE_LEN..]);
+    XChaCha20Poly1305::new_from_slice(&file_key)
+        .map_err(|_| PngError::DecryptionFailed)?
+        .decrypt(nonce, Payload{ msg : ciphertext, aad : &[header, aad].concat() })
+        .map_err(|_| PngError::DecryptionFailed)
+}
+
+/// Whether `data` starts like a payload from `seal_to_recipients`.
+pub fn is_recipient_sealed(data: &[u8]) -> bool
+{
+    data.starts_with(&RECIPIENT_MAGIC)
+}
+
+fn wrap_key(shared: &[u8; 32], ephemeral_pub: &x25519_dalek::PublicKey, recipient: &x25519_dalek::PublicKey) -> [u8; KEY_LEN]
+{
+    let mut salt = [0; 64];
+    salt[..32].copy_from_slice(ephemeral_pub.as_bytes());
+    salt[32..].copy_from_slice(recipient.as_bytes());
+    let mut key = [0; KEY_LEN];
+    Hkdf::<Sha256>::new(Some(&salt), shared)
+        .expand(WRAP_INFO, &mut key)
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Cheap parameters so the tests stay fast in debug builds.
+    const TEST_PARAMS : KdfParams = KdfParams{ m_cost : 64, t_cost : 1, p_cost : 1 };
+
+    #[test]
+    fn test_round_trip() {
+        let sealed = seal_with_params(b"secret message", "hunter2", TEST_PARAMS, b"").unwrap();
+        assert!(is_passphrase_sealed(&sealed));
+        assert_eq!(open_with_passphrase(&sealed, "hunter2", b"").unwrap(), b"secret message");
+        assert!(matches!(open_with_passphrase(&sealed, "hunter2", b"other"), Err(PngError::DecryptionFailed)));
+    }
+
+    #[test]
+    fn test_wrong_passphrase() {
+        let sealed = seal_with_params(b"secret message", "hunter2", TEST_PARAMS, b"").unwrap();
+        assert!(matches!(open_with_passphrase(&sealed, "hunter3", b""), Err(PngError::DecryptionFailed)));
+    }
+
+    #[test]
+    fn test_tampered() {
+        let sealed = seal_with_params(b"secret message", "hunter2", TEST_PARAMS, b"").unwrap();
+
+        let mut body = sealed.clone();
+        let last = body.len() - 1;
+        body[last] ^= 1;
+        assert!(matches!(open_with_passphrase(&body, "hunter2", b""), Err(PngError::DecryptionFailed)));
+
+        let mut salt = sealed.clone();
+        salt[20] ^= 1;
+        assert!(matches!(open_with_passphrase(&salt, "hunter2", b""), Err(PngError::DecryptionFailed)));
+
+        let truncated = &sealed[..HEADER_LEN];
+        assert!(matches!(open_with_passphrase(truncated, "hunter2", b""), Err(PngError::MalformedPayload{ .. })));
+    }
+
+    #[test]
+    fn test_expensive_params() {
+        let mut sealed = seal_with_params(b"secret message", "hunter2", TEST_PARAMS, b"").unwrap();
+        sealed[5..9].copy_from_slice(&(MAX_M_COST + 1).to_be_bytes());
+        assert!(matches!(open_with_passphrase(&sealed, "hunter2", b""), Err(PngError::MalformedPayload{ .. })));
+    }
+
+    #[test]
+    fn test_recipients_round_trip() {
+        let alice = SecretKey::generate();
+        let bob = SecretKey::generate();
+        let sealed = seal_to_recipients(b"team secret", &[alice.public_key(), bob.public_key()], b"").unwrap();
+
+        assert!(is_recipient_sealed(&sealed));
+        assert_eq!(open_with_identity(&sealed, &alice, b"").unwrap(), b"team secret");
+        assert_eq!(open_with_identity(&sealed, &bob, b"").unwrap(), b"team secret");
+        assert!(matches!(open_with_identity(&sealed, &bob, b"other"), Err(PngError::DecryptionFailed)));
+    }
+
+    #[test]
+    fn test_not_a_recipient() {
+        let alice = SecretKey::generate();
+        let eve = SecretKey::generate();
+        let sealed = seal_to_recipients(b"team secret", &[alice.public_key()], b"").unwrap();
+        assert!(matches!(open_with_identity(&sealed, &eve, b""), Err(PngError::DecryptionFailed)));
+
+        let mut tampered = sealed.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 1;
+        assert!(matches!(open_with_identity(&tampered, &alice, b""), Err(PngError::DecryptionFailed)));
+    }
+
+    #[test]
+    fn test_key_strings() {
+        let secret = SecretKey::generate();
+        let public = secret.public_key();
+
+        let parsed = SecretKey::from_str(&secret.to_string()).unwrap();
+        assert_eq!(parsed.public_key(), public);
+        assert_eq!(PublicKey::from_str(&public.to_string()).unwrap(), public);
+
+        assert!(PublicKey::from_str(&secret.to_string()).is_err());
+        assert!(PublicKey::from_str("pngme-pub:abcd").is_err());
+    }
+}
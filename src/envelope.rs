@@ -0,0 +1,334 @@
+use crc::Crc;
+
+use crate::Result;
+use crate::crypto::{self, PublicKey, SecretKey};
+use crate::error::PngError;
+use crate::zlib;
+
+/// Starts every pngme payload. The high first byte keeps it from reading as text.
+pub const ENVELOPE_MAGIC : [u8; 4] = [0x89, b'P', b'M', b'e'];
+pub const ENVELOPE_VERSION : u8 = 1;
+
+/// The body is zlib-compressed.
+pub const FLAG_COMPRESSED : u8 = 0x01;
+/// The body is sealed with `crypto::seal_with_passphrase`.
+pub const FLAG_PASSPHRASE : u8 = 0x02;
+/// The body is sealed with `crypto::seal_to_recipients`.
+pub const FLAG_RECIPIENTS : u8 = 0x04;
+const KNOWN_FLAGS : u8 = FLAG_COMPRESSED | FLAG_PASSPHRASE | FLAG_RECIPIENTS;
+
+/// Largest payload `Envelope::open` inflates a compressed body to.
+pub const DEFAULT_MAX_LEN : u64 = 64 * 1024 * 1024;
+
+pub const TEXT_CONTENT_TYPE : &str = "text/plain; charset=utf-8";
+pub const BINARY_CONTENT_TYPE : &str = "application/octet-stream";
+
+/// How `Envelope::seal` protects the payload.
+pub enum Encryption{
+    None,
+    Passphrase(String),
+    Recipients(Vec<PublicKey>),
+}
+
+/// A self-describing pngme payload, stored as the data of a chunk.
+///
+/// Layout: magic, version, flags, content type (one length byte then UTF-8),
+/// original length (u64), CRC-32 of the original payload (u32), then the body.
+/// The body is the payload after the processing the flags name.
+///
+/// An encrypted body instead has its own length and CRC-32 stored, since
+/// those of the payload would give it away, and the header up to the content
+/// type is authenticated along with it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Envelope{
+    pub flags : u8,
+    pub content_type : String,
+    /// Length of the payload before compression, or of the body if it is encrypted.
+    pub original_len : u64,
+    /// CRC-32 of the payload before compression, or of the body if it is encrypted.
+    pub checksum : u32,
+    pub body : Vec<u8>,
+}
+
+impl Envelope{
+    /// Wrap `payload`, zlib-compressing it first when `compress` is set.
+    pub fn seal(payload: &[u8], content_type: &str, compress: bool, encryption: &Encryption) -> Result<Envelope>{
+        if content_type.len() > u8::MAX as usize
+        {
+            return Err(PngError::MalformedPayload{ reason : "content type longer than 255 bytes".to_string() })
+        }
+        let mut flags = 0;
+        let mut body = payload.to_vec();
+        if compress
+        {
+            body = zlib::deflate(payload)?;
+            flags |= FLAG_COMPRESSED;
+        }
+        match encryption {
+            Encryption::None => {}
+            Encryption::Passphrase(_) => flags |= FLAG_PASSPHRASE,
+            Encryption::Recipients(_) => flags |= FLAG_RECIPIENTS,
+        }
+        let mut envelope = Envelope{
+            flags,
+            content_type : content_type.to_string(),
+            original_len : payload.len() as u64,
+            checksum : checksum(payload),
+            body : Vec::new(),
+        };
+        let aad = envelope.header_bytes();
+        envelope.body = match encryption {
+            Encryption::None => body,
+            Encryption::Passphrase(pass) => crypto::seal_with_passphrase(&body, pass, &aad)?,
+            Encryption::Recipients(keys) => crypto::seal_to_recipients(&body, keys, &aad)?,
+        };
+        if envelope.is_encrypted()
+        {
+            envelope.original_len = envelope.body.len() as u64;
+            envelope.checksum = checksum(&envelope.body);
+        }
+        Ok(envelope)
+    }
+
+    /// Undo the processing named by the flags and check the result against
+    /// the stored length and checksum, or the body against them when it is
+    /// encrypted. Compressed bodies are inflated to at most `DEFAULT_MAX_LEN` bytes.
+    pub fn open(&self, passphrase: Option<&str>, identity: Option<&SecretKey>) -> Result<Vec<u8>>{
+        self.open_with_limit(passphrase, identity, DEFAULT_MAX_LEN)
+    }
+
+    /// Like `open`, but refuses to inflate a compressed body past `max_len` bytes.
+    pub fn open_with_limit(&self, passphrase: Option<&str>, identity: Option<&SecretKey>, max_len: u64) -> Result<Vec<u8>>{
+        let encrypted = self.is_encrypted();
+        if encrypted && !self.matches_checksum(&self.body)
+        {
+            return Err(PngError::MalformedPayload{ reason : "encrypted body does not match its checksum".to_string() })
+        }
+        let mut payload = if self.flags & FLAG_PASSPHRASE != 0
+        {
+            let pass = passphrase.ok_or_else(|| PngError::InvalidKey{ reason : "the message needs a passphrase".to_string() })?;
+            crypto::open_with_passphrase(&self.body, pass, &self.header_bytes())?
+        }
+        else if self.flags & FLAG_RECIPIENTS != 0
+        {
+            let identity = identity.ok_or_else(|| PngError::InvalidKey{ reason : "the message needs a secret key".to_string() })?;
+            crypto::open_with_identity(&self.body, identity, &self.header_bytes())?
+        }
+        else
+        {
+            self.body.clone()
+        };
+
+        if self.flags & FLAG_COMPRESSED != 0
+        {
+            if !encrypted && self.original_len > max_len
+            {
+                return Err(PngError::DecompressedTooLarge{ max : max_len })
+            }
+            payload = zlib::inflate(&payload, max_len)?;
+        }
+
+        if !encrypted && !self.matches_checksum(&payload)
+        {
+            return Err(PngError::MalformedPayload{ reason : "payload does not match its checksum".to_string() })
+        }
+        Ok(payload)
+    }
+
+    fn matches_checksum(&self, bytes: &[u8]) -> bool{
+        bytes.len() as u64 == self.original_len && checksum(bytes) == self.checksum
+    }
+
+    pub fn is_compressed(&self) -> bool{
+        self.flags & FLAG_COMPRESSED != 0
+    }
+
+    pub fn needs_passphrase(&self) -> bool{
+        self.flags & FLAG_PASSPHRASE != 0
+    }
+
+    pub fn needs_identity(&self) -> bool{
+        self.flags & FLAG_RECIPIENTS != 0
+    }
+
+    pub fn is_encrypted(&self) -> bool{
+        self.flags & (FLAG_PASSPHRASE | FLAG_RECIPIENTS) != 0
+    }
+
+    /// Whether `data` starts with the envelope magic. `Envelope::try_from` still has to accept it.
+    pub fn is_envelope(data: &[u8]) -> bool{
+        data.starts_with(&ENVELOPE_MAGIC)
+    }
+
+    /// Magic, version, flags and content type: the part of the header an
+    /// encrypted body is bound to.
+    fn header_bytes(&self) -> Vec<u8>{
+        let mut bytes = Vec::with_capacity(7 + self.content_type.len());
+        bytes.extend_from_slice(&ENVELOPE_MAGIC);
+        bytes.push(ENVELOPE_VERSION);
+        bytes.push(self.flags);
+        bytes.push(self.content_type.len() as u8);
+        bytes.extend_from_slice(self.content_type.as_bytes());
+        bytes
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8>{
+        let mut bytes = self.header_bytes();
+        bytes.reserve(12 + self.body.len());
+        bytes.extend_from_slice(&self.original_len.to_be_bytes());
+        bytes.extend_from_slice(&self.checksum.to_be_bytes());
+        bytes.extend_from_slice(&self.body);
+        bytes
+    }
+}
+
+impl TryFrom<&[u8]> for Envelope{
+    type Error = PngError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self>{
+        let malformed = |reason: &str| PngError::MalformedPayload{ reason : reason.to_string() };
+        if !Envelope::is_envelope(bytes)
+        {
+            return Err(malformed("not a pngme envelope"))
+        }
+        if bytes.len() < 7
+        {
+            return Err(malformed("envelope header is truncated"))
+        }
+        if bytes[4] != ENVELOPE_VERSION
+        {
+            return Err(PngError::MalformedPayload{ reason : format!("unknown envelope version {}", bytes[4]) })
+        }
+        let flags = bytes[5];
+        if flags & !KNOWN_FLAGS != 0
+        {
+            return Err(PngError::MalformedPayload{ reason : format!("unknown envelope flags {:#04x}", flags) })
+        }
+        if flags & FLAG_PASSPHRASE != 0 && flags & FLAG_RECIPIENTS != 0
+        {
+            return Err(malformed("envelope claims two kinds of encryption"))
+        }
+
+        let type_end = 7 + bytes[6] as usize;
+        if bytes.len() < type_end + 12
+        {
+            return Err(malformed("envelope header is truncated"))
+        }
+        let content_type = std::str::from_utf8(&bytes[7..type_end])
+            .map_err(|_| malformed("content type is not UTF-8"))?;
+
+        Ok(Envelope{
+            flags,
+            content_type : content_type.to_string(),
+            original_len : u64::from_be_bytes(bytes[type_end..type_end + 8].try_into().unwrap()),
+            checksum : u32::from_be_bytes(bytes[type_end + 8..type_end + 12].try_into().unwrap()),
+            body : bytes[type_end + 12..].to_vec(),
+        })
+    }
+}
+
+fn checksum(payload: &[u8]) -> u32
+{
+    Crc::<u32>::new(&crc::CRC_32_ISO_HDLC).checksum(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let envelope = Envelope::seal(b"hello", TEXT_CONTENT_TYPE, false, &Encryption::None).unwrap();
+        let bytes = envelope.as_bytes();
+        assert!(Envelope::is_envelope(&bytes));
+
+        let parsed = Envelope::try_from(&bytes[..]).unwrap();
+        assert_eq!(parsed, envelope);
+        assert_eq!(&parsed.content_type, TEXT_CONTENT_TYPE);
+        assert_eq!(parsed.original_len, 5);
+        assert_eq!(parsed.open(None, None).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_recipients() {
+        let key = SecretKey::generate();
+        let envelope = Envelope::seal(b"hello", BINARY_CONTENT_TYPE, false, &Encryption::Recipients(vec![key.public_key()])).unwrap();
+        let parsed = Envelope::try_from(&envelope.as_bytes()[..]).unwrap();
+        assert!(parsed.needs_identity());
+        assert!(matches!(parsed.open(None, None), Err(PngError::InvalidKey{ .. })));
+        assert_eq!(parsed.open(None, Some(&key)).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_rejects_garbage() {
+        assert!(Envelope::try_from(&b"hello world"[..]).is_err());
+
+        let mut bytes = Envelope::seal(b"hello", TEXT_CONTENT_TYPE, false, &Encryption::None).unwrap().as_bytes();
+        assert!(Envelope::try_from(&bytes[..10]).is_err());
+
+        bytes[5] = 0x80;
+        assert!(Envelope::try_from(&bytes[..]).is_err());
+    }
+
+    #[test]
+    fn test_bad_checksum() {
+        let mut bytes = Envelope::seal(b"hello", TEXT_CONTENT_TYPE, false, &Encryption::None).unwrap().as_bytes();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        let parsed = Envelope::try_from(&bytes[..]).unwrap();
+        assert!(matches!(parsed.open(None, None), Err(PngError::MalformedPayload{ .. })));
+    }
+
+    #[test]
+    fn test_compressed() {
+        let payload = "all work and no play ".repeat(100);
+        let envelope = Envelope::seal(payload.as_bytes(), TEXT_CONTENT_TYPE, true, &Encryption::None).unwrap();
+        assert!(envelope.is_compressed());
+        assert!(envelope.body.len() < payload.len() / 10);
+
+        let parsed = Envelope::try_from(&envelope.as_bytes()[..]).unwrap();
+        assert_eq!(parsed.open(None, None).unwrap(), payload.as_bytes());
+    }
+
+    #[test]
+    fn test_compressed_and_encrypted() {
+        let key = SecretKey::generate();
+        let envelope = Envelope::seal(b"hello hello hello", TEXT_CONTENT_TYPE, true, &Encryption::Recipients(vec![key.public_key()])).unwrap();
+        assert!(envelope.is_compressed() && envelope.needs_identity());
+        assert_eq!(envelope.open(None, Some(&key)).unwrap(), b"hello hello hello");
+    }
+
+    #[test]
+    fn test_encrypted_hides_payload() {
+        let key = SecretKey::generate();
+        let envelope = Envelope::seal(b"1234", TEXT_CONTENT_TYPE, false, &Encryption::Recipients(vec![key.public_key()])).unwrap();
+        let bytes = envelope.as_bytes();
+        let crc = checksum(b"1234").to_be_bytes();
+        assert!(!bytes.windows(4).any(|window| window == crc));
+        assert!(!bytes.windows(8).any(|window| window == 4u64.to_be_bytes()));
+
+        // The content type is bound to the body.
+        let mut tampered = Envelope::try_from(&bytes[..]).unwrap();
+        tampered.content_type = BINARY_CONTENT_TYPE.to_string();
+        assert!(matches!(tampered.open(None, Some(&key)), Err(PngError::DecryptionFailed)));
+
+        let mut corrupted = bytes.clone();
+        let last = corrupted.len() - 1;
+        corrupted[last] ^= 1;
+        let corrupted = Envelope::try_from(&corrupted[..]).unwrap();
+        assert!(matches!(corrupted.open(None, Some(&key)), Err(PngError::MalformedPayload{ .. })));
+    }
+
+    #[test]
+    fn test_decompression_limit() {
+        let payload = vec![0; 100_000];
+        let mut envelope = Envelope::seal(&payload, BINARY_CONTENT_TYPE, true, &Encryption::None).unwrap();
+        let res = envelope.open_with_limit(None, None, 1000);
+        assert!(matches!(res, Err(PngError::DecompressedTooLarge{ max : 1000 })));
+
+        // A lying original length must not get past the cap either.
+        envelope.original_len = 10;
+        let res = envelope.open_with_limit(None, None, 1000);
+        assert!(matches!(res, Err(PngError::DecompressedTooLarge{ max : 1000 })));
+    }
+}
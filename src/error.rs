@@ -0,0 +1,165 @@
+use std::fmt;
+use std::io;
+use std::string::FromUtf8Error;
+
+use crate::layout::LayoutViolation;
+
+/// A chunk whose stored CRC does not match its type and data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrcMismatch{
+    /// Position of the chunk in the chunk list.
+    pub index : usize,
+    pub chunk_type : String,
+    /// Byte offset of the chunk's length field in the file.
+    pub offset : usize,
+    /// CRC computed from the chunk type and data.
+    pub expected : u32,
+    /// CRC stored in the file.
+    pub actual : u32,
+}
+
+impl fmt::Display for CrcMismatch{
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result{
+        write!(f, "Crc mismatch in chunk {} ({}) at offset {}: expected {:#010x}, found {:#010x}",
+                self.index, self.chunk_type, self.offset, self.expected, self.actual)
+    }
+}
+
+/// Every way reading, editing or writing a png can fail.
+#[derive(Debug)]
+pub enum PngError{
+    /// The file does not start with the png signature.
+    BadSignature{ found : Vec<u8> },
+    /// A chunk runs past the end of the input.
+    TruncatedChunk{ offset : usize, needed : usize, available : usize },
+    CrcMismatch(CrcMismatch),
+    /// A chunk type that is not four ASCII letters, with the offset of its
+    /// chunk when it was read from a file.
+    InvalidChunkType{ bytes : Vec<u8>, offset : Option<usize> },
+    /// An IHDR chunk with an illegal layout or combination of values.
+    InvalidHeader{ reason : String },
+    /// An edit that would break the spec's chunk ordering rules.
+    InvalidLayout{ violations : Vec<LayoutViolation> },
+    /// A chunk the operation relies on is not in the file.
+    MissingChunk{ chunk_type : String },
+    /// A chunk index past the end of the chunk list.
+    IndexOutOfRange{ index : usize, len : usize },
+    /// Chunk data read as text that is not valid UTF-8.
+    InvalidUtf8(FromUtf8Error),
+    /// A hidden message could not be decrypted: wrong key or tampered data.
+    DecryptionFailed,
+    /// A hidden message that does not follow the format it claims.
+    MalformedPayload{ reason : String },
+    /// A split message with parts that are not in the file. `missing` names
+    /// the first few absent sequence numbers.
+    MissingParts{ message_id : String, missing : Vec<u32>, found : u32, total : u32 },
+    /// A split message with two parts claiming the same sequence number.
+    DuplicatePart{ message_id : String, sequence : u32 },
+    /// A keyword or text that a textual chunk cannot hold.
+    InvalidText{ reason : String },
+    /// A tEXt, zTXt or iTXt chunk that does not follow its layout.
+    MalformedText{ chunk_type : String, reason : String },
+    /// Input that goes past a fixed limit of the format.
+    LimitExceeded{ limit : &'static str, max : u64 },
+    /// A chunk longer than `ParseLimits::max_chunk_length`.
+    ChunkTooLong{ offset : usize, chunk_type : String, length : u32, max : u32 },
+    /// A file with more chunks than `ParseLimits::max_chunk_count`.
+    TooManyChunks{ max : usize },
+    /// Ancillary chunks holding more data than `ParseLimits::max_ancillary_bytes`.
+    AncillaryTooLarge{ max : u64 },
+    /// Compressed data that inflates past `ParseLimits::max_decompressed_size`.
+    DecompressedTooLarge{ max : u64 },
+    /// A public or secret key that cannot be parsed or used.
+    InvalidKey{ reason : String },
+    Io(io::Error),
+}
+
+impl fmt::Display for PngError{
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result{
+        match self {
+            PngError::BadSignature{ found } =>
+                write!(f, "Bad png signature {:?}", found),
+            PngError::TruncatedChunk{ offset, needed, available } =>
+                write!(f, "Truncated chunk at offset {}: needs {} bytes, only {} left", offset, needed, available),
+            PngError::CrcMismatch(mismatch) =>
+                write!(f, "{}", mismatch),
+            PngError::InvalidChunkType{ bytes, offset : None } =>
+                write!(f, "Invalid chunk type {:?}", String::from_utf8_lossy(bytes)),
+            PngError::InvalidChunkType{ bytes, offset : Some(offset) } =>
+                write!(f, "Invalid chunk type {:?} at offset {}", String::from_utf8_lossy(bytes), offset),
+            PngError::InvalidHeader{ reason } =>
+                write!(f, "Invalid image header: {}", reason),
+            PngError::InvalidLayout{ violations } => {
+                let violations: Vec<String> = violations.iter().map(|v| v.to_string()).collect();
+                write!(f, "Invalid chunk layout: {}", violations.join("; "))
+            }
+            PngError::MissingChunk{ chunk_type } =>
+                write!(f, "No {} chunk found", chunk_type),
+            PngError::IndexOutOfRange{ index, len } =>
+                write!(f, "Chunk index {} out of range for {} chunks", index, len),
+            PngError::InvalidUtf8(err) =>
+                write!(f, "Chunk data is not valid UTF-8: {}", err),
+            PngError::DecryptionFailed =>
+                write!(f, "Could not decrypt the message: wrong key or tampered data"),
+            PngError::MalformedPayload{ reason } =>
+                write!(f, "Malformed message payload: {}", reason),
+            PngError::MissingParts{ message_id, missing, found, total } => {
+                let missing: Vec<String> = missing.iter().map(|n| n.to_string()).collect();
+                write!(f, "Message {} has {} of {} parts, missing part {}", message_id, found, total, missing.join(", "))?;
+                if total.saturating_sub(*found) as usize > missing.len()
+                {
+                    write!(f, ", ...")?;
+                }
+                Ok(())
+            }
+            PngError::DuplicatePart{ message_id, sequence } =>
+                write!(f, "Message {} has more than one part {}", message_id, sequence),
+            PngError::InvalidText{ reason } =>
+                write!(f, "Invalid text: {}", reason),
+            PngError::MalformedText{ chunk_type, reason } =>
+                write!(f, "Malformed {} chunk: {}", chunk_type, reason),
+            PngError::LimitExceeded{ limit, max } =>
+                write!(f, "Input exceeds the {} limit of {}", limit, max),
+            PngError::ChunkTooLong{ offset, chunk_type, length, max } =>
+                write!(f, "Chunk {} at offset {} is {} bytes, over the limit of {}", chunk_type, offset, length, max),
+            PngError::TooManyChunks{ max } =>
+                write!(f, "File has more than {} chunks", max),
+            PngError::AncillaryTooLarge{ max } =>
+                write!(f, "Ancillary chunks hold more than {} bytes", max),
+            PngError::DecompressedTooLarge{ max } =>
+                write!(f, "Compressed data inflates to more than {} bytes", max),
+            PngError::InvalidKey{ reason } =>
+                write!(f, "Invalid key: {}", reason),
+            PngError::Io(err) =>
+                write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for PngError{
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)>{
+        match self {
+            PngError::InvalidUtf8(err) => Some(err),
+            PngError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for PngError{
+    fn from(err : io::Error) -> Self{
+        PngError::Io(err)
+    }
+}
+
+impl From<FromUtf8Error> for PngError{
+    fn from(err : FromUtf8Error) -> Self{
+        PngError::InvalidUtf8(err)
+    }
+}
+
+impl From<CrcMismatch> for PngError{
+    fn from(mismatch : CrcMismatch) -> Self{
+        PngError::CrcMismatch(mismatch)
+    }
+}
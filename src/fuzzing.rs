@@ -0,0 +1,109 @@
+//! The entry point of the fuzz target in `fuzz/`, shared with the regression
+//! tests below so every crasher found stays fixed.
+
+use std::io::Cursor;
+
+use crate::envelope::Envelope;
+use crate::ihdr::ImageHeader;
+use crate::ops::{self, Occurrence};
+use crate::png::{ParseLimits, ParseOptions, Png};
+use crate::reader::PngReader;
+use crate::split::Part;
+use crate::text::TextChunk;
+use crate::chunk::Chunk;
+
+/// Limit on decompressed payloads while fuzzing, to keep runs fast.
+const MAX_LEN : u64 = 1 << 20;
+
+/// Run `data` through every parser that sees untrusted input and ignore the
+/// results. Only a panic counts as a failure, including the one when a
+/// lossless parse does not give back `data`.
+#[doc(hidden)]
+pub fn parse_everything(data: &[u8])
+{
+    let _ = Chunk::try_from(data);
+    let _ = Png::try_from(data);
+    // Nothing but the limits stops a lossless parse, and there are none here.
+    let png = Png::parse_lossless(data, &ParseLimits::unlimited()).expect("lossless parse failed");
+    assert!(png.as_bytes() == data, "lossless parse changed the bytes");
+    if let Ok(png) = Png::parse(data, &ParseOptions{ lenient : true, ..Default::default() })
+    {
+        let _ = png.validate();
+        let _ = png.header_info();
+        let _ = png.as_bytes();
+        for chunk in png.chunks()
+        {
+            parse_chunk(chunk);
+        }
+    }
+
+    if let Ok(reader) = PngReader::with_options(Cursor::new(data), ParseOptions{ lenient : true, ..Default::default() })
+    {
+        let mut reader = reader.skip_idat(true);
+        for chunk in reader.by_ref()
+        {
+            if chunk.is_err()
+            {
+                break;
+            }
+        }
+        let _ = reader.layout_violations();
+        let _ = reader.read_trailing_data();
+    }
+    if let Ok(mut reader) = PngReader::new(Cursor::new(data))
+    {
+        if let Ok(envelopes) = ops::find_envelopes(&mut reader, Occurrence::All)
+        {
+            for envelope in envelopes
+            {
+                let _ = envelope.open_with_limit(None, None, MAX_LEN);
+            }
+        }
+    }
+}
+
+fn parse_chunk(chunk: &Chunk)
+{
+    let _ = chunk.to_string();
+    let _ = ImageHeader::try_from(chunk).map(|header| header.to_string());
+    let _ = TextChunk::try_from(chunk).map(|text| text.to_string());
+    if let Ok(envelope) = Envelope::try_from(&chunk.data()[..])
+    {
+        let _ = envelope.open_with_limit(None, None, MAX_LEN);
+    }
+    let _ = Part::try_from(&chunk.data()[..]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+
+    /// Every input in `fuzz/regressions` once made a parser panic.
+    #[test]
+    fn test_regression_corpus() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz/regressions");
+        let mut count = 0;
+        for entry in fs::read_dir(dir).unwrap()
+        {
+            let path = entry.unwrap().path();
+            let data = fs::read(&path).unwrap();
+            let res = std::panic::catch_unwind(|| parse_everything(&data));
+            assert!(res.is_ok(), "{} panics", path.display());
+            count += 1;
+        }
+        assert!(count > 0);
+    }
+
+    #[test]
+    fn test_short_inputs() {
+        // Every prefix of a real file, which covers truncation at each field.
+        let mut png = Png::STANDARD_HEADER.to_vec();
+        png.extend_from_slice(&ImageHeader::try_from(&[0, 0, 0, 1, 0, 0, 0, 1, 8, 2, 0, 0, 0][..]).unwrap().to_chunk().as_bytes());
+        for len in 0..=png.len()
+        {
+            parse_everything(&png[..len]);
+        }
+    }
+}
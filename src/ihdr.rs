@@ -0,0 +1,228 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::Result;
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::error::PngError;
+
+/// How pixels are stored, from the IHDR color type byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorType{
+    Grayscale = 0,
+    Rgb = 2,
+    Indexed = 3,
+    GrayscaleAlpha = 4,
+    Rgba = 6,
+}
+
+impl ColorType{
+    /// Samples per pixel.
+    pub fn channels(&self) -> u8{
+        match self {
+            ColorType::Grayscale | ColorType::Indexed => 1,
+            ColorType::GrayscaleAlpha => 2,
+            ColorType::Rgb => 3,
+            ColorType::Rgba => 4,
+        }
+    }
+
+    /// The bit depths the spec allows with this color type.
+    pub fn allowed_bit_depths(&self) -> &'static [u8]{
+        match self {
+            ColorType::Grayscale => &[1, 2, 4, 8, 16],
+            ColorType::Indexed => &[1, 2, 4, 8],
+            ColorType::Rgb | ColorType::GrayscaleAlpha | ColorType::Rgba => &[8, 16],
+        }
+    }
+}
+
+impl TryFrom<u8> for ColorType{
+    type Error = PngError;
+
+    fn try_from(byte: u8) -> Result<Self>{
+        match byte {
+            0 => Ok(ColorType::Grayscale),
+            2 => Ok(ColorType::Rgb),
+            3 => Ok(ColorType::Indexed),
+            4 => Ok(ColorType::GrayscaleAlpha),
+            6 => Ok(ColorType::Rgba),
+            _ => Err(PngError::InvalidHeader{ reason : format!("unknown color type {}", byte) }),
+        }
+    }
+}
+
+impl fmt::Display for ColorType{
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result{
+        let name = match self {
+            ColorType::Grayscale => "grayscale",
+            ColorType::Rgb => "RGB",
+            ColorType::Indexed => "indexed",
+            ColorType::GrayscaleAlpha => "grayscale with alpha",
+            ColorType::Rgba => "RGB with alpha",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The image properties stored in the IHDR chunk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageHeader{
+    pub width : u32,
+    pub height : u32,
+    pub bit_depth : u8,
+    pub color_type : ColorType,
+    /// Always 0, deflate, in a valid file.
+    pub compression_method : u8,
+    /// Always 0, adaptive filtering, in a valid file.
+    pub filter_method : u8,
+    /// Whether the image is Adam7 interlaced.
+    pub interlaced : bool,
+}
+
+impl ImageHeader{
+    /// Length of the IHDR chunk data.
+    pub const LENGTH : usize = 13;
+    /// Largest width or height the spec allows, 2^31-1.
+    pub const MAX_DIMENSION : u32 = (1 << 31) - 1;
+
+    /// Bits used by one pixel.
+    pub fn bits_per_pixel(&self) -> u32{
+        self.color_type.channels() as u32 * self.bit_depth as u32
+    }
+
+    pub fn to_chunk(&self) -> Chunk{
+        let mut data = Vec::with_capacity(ImageHeader::LENGTH);
+        data.extend_from_slice(&self.width.to_be_bytes());
+        data.extend_from_slice(&self.height.to_be_bytes());
+        data.push(self.bit_depth);
+        data.push(self.color_type as u8);
+        data.push(self.compression_method);
+        data.push(self.filter_method);
+        data.push(self.interlaced as u8);
+        Chunk::new(ChunkType::from_str("IHDR").unwrap(), data).unwrap()
+    }
+}
+
+impl TryFrom<&[u8]> for ImageHeader{
+    type Error = PngError;
+
+    /// Parse and validate IHDR chunk data.
+    fn try_from(data: &[u8]) -> Result<Self>{
+        let invalid = |reason: String| Err(PngError::InvalidHeader{ reason });
+        if data.len() != ImageHeader::LENGTH
+        {
+            return invalid(format!("IHDR is {} bytes, not {}", data.len(), ImageHeader::LENGTH))
+        }
+        let width = u32::from_be_bytes(data[0..4].try_into().unwrap());
+        let height = u32::from_be_bytes(data[4..8].try_into().unwrap());
+        for (name, value) in [("width", width), ("height", height)]
+        {
+            if value == 0 || value > ImageHeader::MAX_DIMENSION
+            {
+                return invalid(format!("{} {} is not between 1 and 2^31-1", name, value))
+            }
+        }
+        let bit_depth = data[8];
+        let color_type = ColorType::try_from(data[9])?;
+        if !color_type.allowed_bit_depths().contains(&bit_depth)
+        {
+            return invalid(format!("bit depth {} is not allowed with color type {} ({})", bit_depth, color_type as u8, color_type))
+        }
+        if data[10] != 0
+        {
+            return invalid(format!("unknown compression method {}", data[10]))
+        }
+        if data[11] != 0
+        {
+            return invalid(format!("unknown filter method {}", data[11]))
+        }
+        if data[12] > 1
+        {
+            return invalid(format!("unknown interlace method {}", data[12]))
+        }
+        Ok(ImageHeader{
+            width,
+            height,
+            bit_depth,
+            color_type,
+            compression_method : data[10],
+            filter_method : data[11],
+            interlaced : data[12] == 1,
+        })
+    }
+}
+
+impl TryFrom<&Chunk> for ImageHeader{
+    type Error = PngError;
+
+    fn try_from(chunk: &Chunk) -> Result<Self>{
+        if &chunk.chunk_type().bytes() != b"IHDR"
+        {
+            return Err(PngError::InvalidHeader{ reason : format!("{} is not an IHDR chunk", chunk.chunk_type()) })
+        }
+        ImageHeader::try_from(&chunk.data()[..])
+    }
+}
+
+impl fmt::Display for ImageHeader{
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result{
+        writeln!(f, "Width       : {}", self.width)?;
+        writeln!(f, "Height      : {}", self.height)?;
+        writeln!(f, "Bit depth   : {}", self.bit_depth)?;
+        writeln!(f, "Color type  : {} ({})", self.color_type, self.color_type as u8)?;
+        writeln!(f, "Compression : deflate ({})", self.compression_method)?;
+        writeln!(f, "Filter      : adaptive ({})", self.filter_method)?;
+        write!(f, "Interlace   : {} ({})", if self.interlaced { "Adam7" } else { "none" }, self.interlaced as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header_bytes(width: u32, height: u32, bit_depth: u8, color_type: u8) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&width.to_be_bytes());
+        data.extend_from_slice(&height.to_be_bytes());
+        data.extend_from_slice(&[bit_depth, color_type, 0, 0, 0]);
+        data
+    }
+
+    #[test]
+    fn test_parse() {
+        let header = ImageHeader::try_from(&header_bytes(640, 480, 8, 6)[..]).unwrap();
+        assert_eq!((header.width, header.height), (640, 480));
+        assert_eq!(header.color_type, ColorType::Rgba);
+        assert_eq!(header.bits_per_pixel(), 32);
+        assert!(!header.interlaced);
+        assert_eq!(ImageHeader::try_from(&header.to_chunk()).unwrap(), header);
+    }
+
+    #[test]
+    fn test_bit_depth_combinations() {
+        let legal = [(0, &[1, 2, 4, 8, 16][..]), (2, &[8, 16]), (3, &[1, 2, 4, 8]), (4, &[8, 16]), (6, &[8, 16])];
+        for color_type in 0..8
+        {
+            for bit_depth in [1, 2, 4, 8, 16, 3, 32]
+            {
+                let ok = legal.iter().any(|(c, depths)| *c == color_type && depths.contains(&bit_depth));
+                let res = ImageHeader::try_from(&header_bytes(1, 1, bit_depth, color_type)[..]);
+                assert_eq!(res.is_ok(), ok, "color type {} bit depth {}", color_type, bit_depth);
+            }
+        }
+    }
+
+    #[test]
+    fn test_rejects_invalid() {
+        assert!(ImageHeader::try_from(&header_bytes(0, 1, 8, 2)[..]).is_err());
+        assert!(ImageHeader::try_from(&header_bytes(1, 1 << 31, 8, 2)[..]).is_err());
+        assert!(ImageHeader::try_from(&header_bytes(1, 1, 8, 2)[..12]).is_err());
+        for (i, value) in [(10, 1), (11, 1), (12, 2)]
+        {
+            let mut data = header_bytes(1, 1, 8, 2);
+            data[i] = value;
+            assert!(matches!(ImageHeader::try_from(&data[..]), Err(PngError::InvalidHeader{ .. })));
+        }
+    }
+}
@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::Result;
+use crate::chunk::Chunk;
+use crate::error::PngError;
+use crate::ihdr::{ColorType, ImageHeader};
+
+/// Chunks that may appear at most once.
+const SINGLE : [&[u8; 4]; 17] = [
+    b"IHDR", b"PLTE", b"IEND", b"cHRM", b"cICP", b"gAMA", b"iCCP", b"mDCV", b"cLLI",
+    b"sBIT", b"sRGB", b"bKGD", b"hIST", b"tRNS", b"pHYs", b"eXIf", b"tIME",
+];
+/// Chunks that must come before PLTE and IDAT.
+const BEFORE_PLTE : [&[u8; 4]; 8] = [b"cHRM", b"cICP", b"gAMA", b"iCCP", b"mDCV", b"cLLI", b"sBIT", b"sRGB"];
+/// Chunks that must come after PLTE, when there is one, and before IDAT.
+const AFTER_PLTE : [&[u8; 4]; 3] = [b"bKGD", b"hIST", b"tRNS"];
+/// Chunks that must come before IDAT.
+const BEFORE_IDAT : [&[u8; 4]; 3] = [b"pHYs", b"sPLT", b"eXIf"];
+
+/// What is wrong with a chunk's place in the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Problem{
+    /// A required chunk is absent.
+    Missing,
+    /// IHDR is not the first chunk.
+    NotFirst,
+    /// A chunk that may appear once appears again.
+    Duplicate,
+    /// The chunk must come before the first chunk of this type.
+    MustPrecede(&'static str),
+    /// The chunk must come after a chunk of this type.
+    MustFollow(&'static str),
+    /// An IDAT chunk separated from the IDAT chunks before it.
+    NotConsecutive,
+    /// PLTE in a grayscale image.
+    NotAllowed,
+}
+
+/// One way a chunk list breaks the ordering and multiplicity rules of the PNG spec.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayoutViolation{
+    pub chunk_type : String,
+    /// Position of the offending chunk, or `None` for a missing one.
+    pub index : Option<usize>,
+    pub problem : Problem,
+}
+
+impl fmt::Display for LayoutViolation{
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result{
+        let at = match self.index {
+            Some(i) => format!("{} at chunk {}", self.chunk_type, i),
+            None => self.chunk_type.clone(),
+        };
+        match self.problem {
+            Problem::Missing => write!(f, "no {} chunk", self.chunk_type),
+            Problem::NotFirst => write!(f, "{} is not the first chunk", at),
+            Problem::Duplicate => write!(f, "{} repeats a chunk that may appear once", at),
+            Problem::MustPrecede(other) => write!(f, "{} must come before {}", at, other),
+            Problem::MustFollow(other) => write!(f, "{} must come after {}", at, other),
+            Problem::NotConsecutive => write!(f, "{} is not next to the other IDAT chunks", at),
+            Problem::NotAllowed => write!(f, "{} is not allowed in a grayscale image", at),
+        }
+    }
+}
+
+/// Checks chunk ordering one chunk at a time, so it works on streams as well
+/// as on a whole `Png`.
+#[derive(Debug, Default)]
+pub struct LayoutChecker{
+    index : usize,
+    counts : HashMap<[u8; 4], usize>,
+    previous : Option<[u8; 4]>,
+    color_type : Option<ColorType>,
+    violations : Vec<LayoutViolation>,
+}
+
+impl LayoutChecker{
+    pub fn new() -> Self{
+        LayoutChecker::default()
+    }
+
+    /// Take in the next chunk.
+    pub fn push(&mut self, chunk: &Chunk){
+        self.push_data(chunk.chunk_type().bytes(), chunk.data());
+    }
+
+    /// Take in the next chunk, given as its type and data.
+    pub fn push_data(&mut self, name: [u8; 4], data: &[u8]){
+        let index = self.index;
+        let seen = |checker: &Self, name: &[u8; 4]| checker.counts.get(name).copied().unwrap_or(0) > 0;
+        let mut problems = Vec::new();
+
+        if seen(self, b"IEND")
+        {
+            problems.push(Problem::MustPrecede("IEND"));
+        }
+        if SINGLE.contains(&&name) && seen(self, &name)
+        {
+            problems.push(Problem::Duplicate);
+        }
+        match &name {
+            b"IHDR" => {
+                if index != 0
+                {
+                    problems.push(Problem::NotFirst);
+                }
+                if self.color_type.is_none()
+                {
+                    self.color_type = ImageHeader::try_from(data).ok().map(|header| header.color_type);
+                }
+            }
+            b"IDAT" if seen(self, b"IDAT") && self.previous != Some(*b"IDAT") => {
+                problems.push(Problem::NotConsecutive);
+            }
+            b"PLTE" => {
+                if seen(self, b"IDAT")
+                {
+                    problems.push(Problem::MustPrecede("IDAT"));
+                }
+                if matches!(self.color_type, Some(ColorType::Grayscale | ColorType::GrayscaleAlpha))
+                {
+                    problems.push(Problem::NotAllowed);
+                }
+            }
+            _ if BEFORE_PLTE.contains(&&name) => {
+                if seen(self, b"PLTE")
+                {
+                    problems.push(Problem::MustPrecede("PLTE"));
+                }
+                else if seen(self, b"IDAT")
+                {
+                    problems.push(Problem::MustPrecede("IDAT"));
+                }
+            }
+            _ if AFTER_PLTE.contains(&&name) => {
+                if seen(self, b"IDAT")
+                {
+                    problems.push(Problem::MustPrecede("IDAT"));
+                }
+                let needs_plte = &name == b"hIST" || self.color_type == Some(ColorType::Indexed);
+                if needs_plte && !seen(self, b"PLTE")
+                {
+                    problems.push(Problem::MustFollow("PLTE"));
+                }
+            }
+            _ if BEFORE_IDAT.contains(&&name) && seen(self, b"IDAT") => {
+                problems.push(Problem::MustPrecede("IDAT"));
+            }
+            _ => {}
+        }
+
+        let chunk_type = String::from_utf8_lossy(&name).into_owned();
+        self.violations.extend(problems.into_iter().map(|problem| LayoutViolation{
+            chunk_type : chunk_type.clone(),
+            index : Some(index),
+            problem,
+        }));
+        *self.counts.entry(name).or_insert(0) += 1;
+        self.previous = Some(name);
+        self.index += 1;
+    }
+
+    /// Every violation among the chunks taken in, counting required chunks
+    /// that never came as missing.
+    pub fn violations(&self) -> Vec<LayoutViolation>{
+        let mut violations = self.violations.clone();
+        let mut required = vec!["IHDR", "IDAT", "IEND"];
+        if self.color_type == Some(ColorType::Indexed)
+        {
+            required.insert(1, "PLTE");
+        }
+        for name in required
+        {
+            let name_b: [u8; 4] = name.as_bytes().try_into().unwrap();
+            if !self.counts.contains_key(&name_b)
+            {
+                violations.push(LayoutViolation{ chunk_type : name.to_string(), index : None, problem : Problem::Missing });
+            }
+        }
+        violations
+    }
+}
+
+/// Every layout violation in `chunks`.
+pub fn check<'a>(chunks: impl IntoIterator<Item = &'a Chunk>) -> Vec<LayoutViolation>
+{
+    let mut checker = LayoutChecker::new();
+    for chunk in chunks
+    {
+        checker.push(chunk);
+    }
+    checker.violations()
+}
+
+/// The violations in `after` that `before` did not already have, matched by
+/// chunk type and problem since positions shift when chunks are added or removed.
+pub fn introduced(before: &[LayoutViolation], after: &[LayoutViolation]) -> Vec<LayoutViolation>
+{
+    let mut existing: HashMap<(&str, Problem), usize> = HashMap::new();
+    for violation in before
+    {
+        *existing.entry((&violation.chunk_type, violation.problem)).or_insert(0) += 1;
+    }
+    after.iter()
+        .filter(|violation| {
+            match existing.get_mut(&(&violation.chunk_type[..], violation.problem)) {
+                Some(n) if *n > 0 => {
+                    *n -= 1;
+                    false
+                }
+                _ => true,
+            }
+        })
+        .cloned()
+        .collect()
+}
+
+/// Fail with `InvalidLayout` if `after` has violations `before` did not.
+pub fn ensure_no_new(before: &[LayoutViolation], after: &[LayoutViolation]) -> Result<()>
+{
+    let introduced = introduced(before, after);
+    if !introduced.is_empty()
+    {
+        return Err(PngError::InvalidLayout{ violations : introduced })
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    fn chunks(names: &[&str]) -> Vec<Chunk> {
+        names.iter()
+            .map(|name| {
+                // An RGB IHDR, or an indexed one for "IHDR3".
+                let (name, data) = match *name {
+                    "IHDR" => ("IHDR", vec![0, 0, 0, 1, 0, 0, 0, 1, 8, 2, 0, 0, 0]),
+                    "IHDR3" => ("IHDR", vec![0, 0, 0, 1, 0, 0, 0, 1, 8, 3, 0, 0, 0]),
+                    "IHDR0" => ("IHDR", vec![0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0]),
+                    other => (other, Vec::new()),
+                };
+                Chunk::new(ChunkType::from_str(name).unwrap(), data).unwrap()
+            })
+            .collect()
+    }
+
+    fn problems(names: &[&str]) -> Vec<(String, Option<usize>, Problem)> {
+        check(&chunks(names)).into_iter().map(|v| (v.chunk_type, v.index, v.problem)).collect()
+    }
+
+    #[test]
+    fn test_valid_layouts() {
+        assert!(problems(&["IHDR", "gAMA", "pHYs", "IDAT", "IDAT", "tEXt", "IEND"]).is_empty());
+        assert!(problems(&["IHDR3", "sRGB", "PLTE", "tRNS", "IDAT", "IEND"]).is_empty());
+    }
+
+    #[test]
+    fn test_missing_chunks() {
+        let found = problems(&["tEXt"]);
+        let missing: Vec<&str> = found.iter().filter(|p| p.2 == Problem::Missing).map(|p| &p.0[..]).collect();
+        assert_eq!(missing, ["IHDR", "IDAT", "IEND"]);
+        assert!(problems(&["IHDR3", "IDAT", "IEND"]).contains(&("PLTE".to_string(), None, Problem::Missing)));
+    }
+
+    #[test]
+    fn test_every_violation_is_reported() {
+        let found = problems(&["tEXt", "IHDR", "IDAT", "PLTE", "gAMA", "IDAT", "IEND", "IEND", "ruSt"]);
+        assert_eq!(found, [
+            ("IHDR".to_string(), Some(1), Problem::NotFirst),
+            ("PLTE".to_string(), Some(3), Problem::MustPrecede("IDAT")),
+            ("gAMA".to_string(), Some(4), Problem::MustPrecede("PLTE")),
+            ("IDAT".to_string(), Some(5), Problem::NotConsecutive),
+            ("IEND".to_string(), Some(7), Problem::MustPrecede("IEND")),
+            ("IEND".to_string(), Some(7), Problem::Duplicate),
+            ("ruSt".to_string(), Some(8), Problem::MustPrecede("IEND")),
+        ]);
+    }
+
+    #[test]
+    fn test_palette_rules() {
+        assert!(problems(&["IHDR0", "PLTE", "IDAT", "IEND"]).contains(&("PLTE".to_string(), Some(1), Problem::NotAllowed)));
+        assert!(problems(&["IHDR3", "tRNS", "PLTE", "IDAT", "IEND"]).contains(&("tRNS".to_string(), Some(1), Problem::MustFollow("PLTE"))));
+        assert!(problems(&["IHDR", "hIST", "IDAT", "IEND"]).contains(&("hIST".to_string(), Some(1), Problem::MustFollow("PLTE"))));
+    }
+
+    #[test]
+    fn test_introduced() {
+        let before = check(&chunks(&["tEXt", "IDAT", "IEND"]));
+        let after = check(&chunks(&["zTXt", "tEXt", "IDAT", "IEND", "ruSt"]));
+        let new = introduced(&before, &after);
+        assert_eq!(new.len(), 1);
+        assert_eq!(new[0].to_string(), "ruSt at chunk 4 must come before IEND");
+    }
+}
@@ -0,0 +1,39 @@
+//! Hide messages inside png files as extra chunks.
+//!
+//! `Png`, `Chunk` and `ChunkType` model the file format, and the functions in
+//! `ops` are the encode/decode operations the `pngme` binary is built on.
+
+pub mod atomic;
+pub mod chunk;
+pub mod chunk_type;
+pub mod crypto;
+pub mod envelope;
+#[cfg(any(test, feature = "fuzzing"))]
+pub mod fuzzing;
+pub mod error;
+pub mod ihdr;
+pub mod layout;
+pub mod ops;
+pub mod png;
+pub mod reader;
+pub mod split;
+pub mod text;
+pub mod writer;
+pub mod zlib;
+
+pub use chunk::Chunk;
+pub use chunk_type::ChunkType;
+pub use envelope::Envelope;
+pub use error::{CrcMismatch, PngError};
+pub use ihdr::{ColorType, ImageHeader};
+pub use layout::LayoutViolation;
+pub use ops::{
+    decode_message, decode_messages, encode_message, encode_split_stream, encode_stream, find_envelopes, find_message, find_messages,
+    remove_message, remove_messages, remove_stream, restore_trailing_stream, trailing_chunks, Occurrence,
+};
+pub use png::{ChunkPosition, ParseLimits, ParseOptions, Png};
+pub use reader::PngReader;
+pub use writer::PngWriter;
+
+pub type Error = PngError;
+pub type Result<T> = std::result::Result<T, Error>;
@@ -1,24 +1,75 @@
 mod args;
-mod chunk;
-mod chunk_type;
 mod commands;
-mod png;
+mod output;
 use clap::Parser;
+use pngme::Error;
+use std::io::ErrorKind;
+use std::process::ExitCode;
 
-pub type Error = Box<dyn std::error::Error>;
-pub type Result<T> = std::result::Result<T, Error>;
+fn main() -> ExitCode {
+    let clap_arg = match args::Cli::try_parse() {
+        Ok(clap_arg) => clap_arg,
+        Err(err) if err.use_stderr() && output::json_requested(&std::env::args().collect::<Vec<_>>()) => {
+            let message = output::usage_message(&err.render().to_string());
+            output::usage_error(args::Format::Json, err.kind(), &message)
+        }
+        Err(err) => err.exit(),
+    };
+    let format = clap_arg.format;
+    let limits = clap_arg.limits.parse_limits();
 
-fn main() -> Result<()> {
-    let clap_arg = args::Cli::parse(); 
+    let res = match &clap_arg.command{
+        args::Commands::Encode(args) => commands::encode(args, format, limits),
+        args::Commands::Decode(args) => commands::decode(args, format, limits),
+        args::Commands::Remove(args) => commands::remove(args, format, limits),
+        args::Commands::Print(args) => commands::print(args, format, limits),
+        args::Commands::Keygen(args) => commands::keygen(args, format),
+        args::Commands::Text(args) => commands::text(args, format, limits),
+        args::Commands::Info(args) => commands::info(args, format, limits),
+        args::Commands::Trailing(args) => commands::trailing(args, format, limits),
+    };
 
-    match &clap_arg.command{
-        args::Commands::Encode(args) => commands::encode(args),
-        args::Commands::Decode(args) => commands::decode(args),
-        args::Commands::Remove(args) => commands::remove(args),
-        args::Commands::Print(args) => commands::print(args),
+    match res {
+        Ok(()) => ExitCode::SUCCESS,
+        // Whatever reads our output stopped early, as `head` does. Not a failure.
+        Err(Error::Io(err)) if err.kind() == ErrorKind::BrokenPipe => ExitCode::SUCCESS,
+        Err(err) => {
+            let code = exit_code(&err);
+            match format {
+                args::Format::Text => eprintln!("Error: {}", err),
+                args::Format::Json => output::failure(&err, code),
+            }
+            ExitCode::from(code)
+        }
     }
-
-
-    Ok(())
 }
 
+/// Exit codes, also listed in the `--help` output:
+/// 1 for other failures, 2 for invalid input, 3 for not found, 4 for a corrupt file.
+fn exit_code(err: &Error) -> u8 {
+    match err {
+        Error::InvalidChunkType{ .. }
+        | Error::IndexOutOfRange{ .. }
+        | Error::InvalidUtf8(_)
+        | Error::DecryptionFailed
+        | Error::InvalidKey{ .. }
+        | Error::InvalidText{ .. }
+        | Error::InvalidLayout{ .. } => 2,
+        Error::MissingChunk{ .. } => 3,
+        Error::Io(io_err) if io_err.kind() == ErrorKind::NotFound => 3,
+        Error::BadSignature{ .. }
+        | Error::TruncatedChunk{ .. }
+        | Error::CrcMismatch(_)
+        | Error::InvalidHeader{ .. }
+        | Error::MalformedPayload{ .. }
+        | Error::MalformedText{ .. }
+        | Error::MissingParts{ .. }
+        | Error::DuplicatePart{ .. }
+        | Error::LimitExceeded{ .. }
+        | Error::ChunkTooLong{ .. }
+        | Error::TooManyChunks{ .. }
+        | Error::AncillaryTooLarge{ .. }
+        | Error::DecompressedTooLarge{ .. } => 4,
+        Error::Io(_) => 1,
+    }
+}
@@ -0,0 +1,680 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+use crate::Result;
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::envelope::Envelope;
+use crate::error::PngError;
+use crate::layout;
+use crate::png::{ChunkPosition, Png};
+use crate::reader::PngReader;
+use crate::split::{self, Part};
+use crate::writer::PngWriter;
+
+/// Which of the messages stored in chunks of one type an operation applies to.
+/// A message split across several chunks counts once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Occurrence{
+    First,
+    /// The nth message, counting from 0.
+    Nth(usize),
+    All,
+}
+
+impl Occurrence{
+    fn matches(&self, n: usize) -> bool{
+        match self {
+            Occurrence::First => n == 0,
+            Occurrence::Nth(i) => n == *i,
+            Occurrence::All => true,
+        }
+    }
+
+    /// The error for an occurrence that was not found among `count` messages.
+    fn missing(&self, chunk_type: &str, count: usize) -> PngError{
+        match self {
+            Occurrence::Nth(i) if count > 0 => PngError::IndexOutOfRange{ index : *i, len : count },
+            _ => PngError::MissingChunk{ chunk_type : chunk_type.to_string() },
+        }
+    }
+}
+
+/// Numbers the messages in a chunk stream. Every part of a split message gets
+/// the number its message was first seen with.
+#[derive(Default)]
+struct MessageCounter{
+    ids : HashMap<[u8; 8], usize>,
+    count : usize,
+}
+
+impl MessageCounter{
+    fn number(&mut self, data: &[u8]) -> usize{
+        let id = Part::id_of(data);
+        if let Some(&n) = id.and_then(|id| self.ids.get(&id))
+        {
+            return n
+        }
+        if let Some(id) = id
+        {
+            self.ids.insert(id, self.count);
+        }
+        self.count += 1;
+        self.count - 1
+    }
+}
+
+/// A message picked from a chunk stream: the data of one chunk, or the parts
+/// of a split message seen so far with their distinct sequence numbers.
+enum Gathered{
+    Whole(Vec<u8>),
+    Split{ parts : Vec<Part>, sequences : HashSet<u32> },
+}
+
+/// Collects the messages picked by an occurrence, chunk by chunk.
+struct Gatherer{
+    occurrence : Occurrence,
+    counter : MessageCounter,
+    picked : Vec<(usize, Gathered)>,
+}
+
+impl Gatherer{
+    fn new(occurrence: Occurrence) -> Self{
+        Gatherer{ occurrence, counter : MessageCounter::default(), picked : Vec::new() }
+    }
+
+    /// Take in the data of a matching chunk. Returns whether the search is over.
+    /// Messages are numbered in the order they are first seen, so `picked`
+    /// stays sorted by number.
+    fn add(&mut self, data: Vec<u8>) -> Result<bool>{
+        let n = self.counter.number(&data);
+        if !self.occurrence.matches(n)
+        {
+            return Ok(false)
+        }
+        if !Part::is_part(&data)
+        {
+            self.picked.push((n, Gathered::Whole(data)));
+            return Ok(self.occurrence != Occurrence::All)
+        }
+
+        let part = Part::try_from(&data[..])?;
+        let index = match self.picked.binary_search_by_key(&n, |(m, _)| *m) {
+            Ok(index) => index,
+            Err(index) => {
+                self.picked.insert(index, (n, Gathered::Split{ parts : Vec::new(), sequences : HashSet::new() }));
+                index
+            }
+        };
+        let Gathered::Split{ parts, sequences } = &mut self.picked[index].1 else { return Ok(false) };
+        sequences.insert(part.sequence);
+        let complete = sequences.len() as u64 == parts.first().unwrap_or(&part).total as u64;
+        parts.push(part);
+        Ok(self.occurrence != Occurrence::All && complete)
+    }
+
+    /// The picked messages in order, split ones reassembled as they are reached.
+    fn into_messages(self) -> impl Iterator<Item = Result<Vec<u8>>>{
+        self.picked.into_iter()
+            .map(|(_, gathered)| match gathered {
+                Gathered::Whole(data) => Ok(data),
+                Gathered::Split{ parts, .. } => split::join(parts),
+            })
+    }
+
+    /// The picked messages, split ones reassembled, or the error for finding none.
+    fn finish(self, chunk_type: &str) -> Result<Vec<Vec<u8>>>{
+        if self.picked.is_empty()
+        {
+            return Err(self.occurrence.missing(chunk_type, self.counter.count))
+        }
+        self.into_messages().collect()
+    }
+}
+
+/// Store `message` in a new chunk of type `chunk_type` at `position`.
+pub fn encode_message(png: &mut Png, chunk_type: &str, message: &[u8], position: ChunkPosition) -> Result<()>
+{
+    let chunkt = ChunkType::from_str(chunk_type)?;
+    let chunk = Chunk::new(chunkt, message.to_vec())?;
+    png.insert_chunk(chunk, position)
+}
+
+/// The first message in chunks of type `chunk_type`.
+pub fn decode_message(png: &Png, chunk_type: &str) -> Result<Vec<u8>>
+{
+    decode_messages(png, chunk_type, Occurrence::First).map(|mut found| found.remove(0))
+}
+
+/// The messages in chunks of type `chunk_type` picked by `occurrence`.
+/// A message split across several chunks comes back reassembled.
+pub fn decode_messages(png: &Png, chunk_type: &str, occurrence: Occurrence) -> Result<Vec<Vec<u8>>>
+{
+    ChunkType::from_str(chunk_type)?;
+    let mut gatherer = Gatherer::new(occurrence);
+    for chunk in png.chunks_by_type(chunk_type)
+    {
+        if gatherer.add(chunk.data().clone())?
+        {
+            break;
+        }
+    }
+    gatherer.finish(chunk_type)
+}
+
+/// Like `decode_message`, but streams `reader` and stops at the first match.
+pub fn find_message<R: Read>(reader: &mut PngReader<R>, chunk_type: &str) -> Result<Vec<u8>>
+{
+    find_messages(reader, chunk_type, Occurrence::First).map(|mut found| found.remove(0))
+}
+
+/// Like `decode_messages`, but streams `reader`, stopping early unless `occurrence` is `All`.
+pub fn find_messages<R: Read>(reader: &mut PngReader<R>, chunk_type: &str, occurrence: Occurrence) -> Result<Vec<Vec<u8>>>
+{
+    let chunkt = ChunkType::from_str(chunk_type)?;
+    let mut gatherer = Gatherer::new(occurrence);
+    for chunk in reader
+    {
+        let chunk = chunk?;
+        if chunk.chunk_type() == &chunkt && gatherer.add(chunk.data)?
+        {
+            break;
+        }
+    }
+    gatherer.finish(chunk_type)
+}
+
+/// Like `find_messages`, but picks the pngme `Envelope`s stored in a chunk or
+/// split across several, whatever their type. A split message is only known
+/// to be an envelope once reassembled, so this reads the whole stream, and
+/// split messages that turn out not to be envelopes are not counted.
+pub fn find_envelopes<R: Read>(reader: &mut PngReader<R>, occurrence: Occurrence) -> Result<Vec<Envelope>>
+{
+    let mut gatherer = Gatherer::new(Occurrence::All);
+    for chunk in reader
+    {
+        let chunk = chunk?;
+        let data = chunk.data();
+        let wanted = if Part::is_part(data) { Part::id_of(data).is_some() } else { Envelope::try_from(&data[..]).is_ok() };
+        if wanted
+        {
+            gatherer.add(chunk.data)?;
+        }
+    }
+
+    let mut found = Vec::new();
+    let mut count = 0;
+    for data in gatherer.into_messages()
+    {
+        let data = data?;
+        if !Envelope::is_envelope(&data)
+        {
+            continue;
+        }
+        if occurrence.matches(count)
+        {
+            found.push(Envelope::try_from(&data[..])?);
+            if occurrence != Occurrence::All
+            {
+                break;
+            }
+        }
+        count += 1;
+    }
+    if found.is_empty()
+    {
+        return Err(occurrence.missing("pngme", count))
+    }
+    Ok(found)
+}
+
+/// Remove the first message in chunks of type `chunk_type`, every part of
+/// it if it is split. Returns the data of the removed chunks.
+pub fn remove_message(png: &mut Png, chunk_type: &str) -> Result<Vec<Vec<u8>>>
+{
+    remove_messages(png, chunk_type, Occurrence::First)
+}
+
+/// Like `remove_stream`, but edits `png` in place.
+pub fn remove_messages(png: &mut Png, chunk_type: &str, occurrence: Occurrence) -> Result<Vec<Vec<u8>>>
+{
+    let chunkt = ChunkType::from_str(chunk_type)?;
+    let mut counter = MessageCounter::default();
+    let picked: Vec<usize> = png.chunks().iter()
+        .enumerate()
+        .filter(|(_, chunk)| chunk.chunk_type() == &chunkt && occurrence.matches(counter.number(chunk.data())))
+        .map(|(i, _)| i)
+        .collect();
+    if picked.is_empty()
+    {
+        return Err(occurrence.missing(chunk_type, counter.count))
+    }
+    Ok(png.remove_chunks_at(&picked)?.into_iter().map(|chunk| chunk.data).collect())
+}
+
+/// Like `encode_message`, but copies `reader` to `writer` one chunk at a time.
+pub fn encode_stream<R: Read, W: Write>(reader: &mut PngReader<R>, writer: &mut PngWriter<W>,
+                                        chunk_type: &str, message: &[u8], position: ChunkPosition) -> Result<()>
+{
+    insert_stream(reader, writer, chunk_type, &[message.to_vec()], position)
+}
+
+/// Like `encode_stream`, but splits `message` into parts of at most
+/// `part_size` bytes, stored in consecutive chunks of type `chunk_type`.
+pub fn encode_split_stream<R: Read, W: Write>(reader: &mut PngReader<R>, writer: &mut PngWriter<W>,
+                                              chunk_type: &str, message: &[u8], part_size: usize,
+                                              position: ChunkPosition) -> Result<()>
+{
+    let parts: Vec<Vec<u8>> = split::split(message, part_size)?.iter().map(Part::as_bytes).collect();
+    insert_stream(reader, writer, chunk_type, &parts, position)
+}
+
+/// Copy `reader` to `writer`, writing one chunk of type `chunk_type` per entry of `datas` at `position`.
+fn insert_stream<R: Read, W: Write>(reader: &mut PngReader<R>, writer: &mut PngWriter<W>,
+                                    chunk_type: &str, datas: &[Vec<u8>], position: ChunkPosition) -> Result<()>
+{
+    let chunkt = ChunkType::from_str(chunk_type)?;
+    let write_all = |writer: &mut PngWriter<W>, chunkt: &ChunkType| -> Result<()> {
+        for data in datas
+        {
+            writer.write_data(chunkt, data)?;
+        }
+        Ok(())
+    };
+    let mut pending = Some(chunkt);
+    let mut index = 0;
+    for chunk in reader.by_ref()
+    {
+        let chunk = chunk?;
+        let name = chunk.chunk_type().bytes();
+        let before = match position {
+            ChunkPosition::BeforeIend => &name == b"IEND",
+            ChunkPosition::BeforeIdat => &name == b"IDAT",
+            ChunkPosition::Index(i) => i == index,
+            ChunkPosition::AfterIhdr => false,
+        };
+        if before
+        {
+            if let Some(chunkt) = pending.take()
+            {
+                write_all(writer, &chunkt)?;
+            }
+        }
+
+        writer.write_chunk(&chunk)?;
+        index += 1;
+
+        if position == ChunkPosition::AfterIhdr && &name == b"IHDR"
+        {
+            if let Some(chunkt) = pending.take()
+            {
+                write_all(writer, &chunkt)?;
+            }
+        }
+    }
+
+    let Some(chunkt) = pending else { return layout::ensure_no_new(&reader.layout_violations(), &writer.layout_violations()) };
+    match position {
+        ChunkPosition::Index(i) if i == index => {
+            write_all(writer, &chunkt)?;
+            layout::ensure_no_new(&reader.layout_violations(), &writer.layout_violations())
+        }
+        ChunkPosition::Index(i) => Err(PngError::IndexOutOfRange{ index : i, len : index }),
+        ChunkPosition::BeforeIend => Err(PngError::MissingChunk{ chunk_type : "IEND".to_string() }),
+        ChunkPosition::AfterIhdr => Err(PngError::MissingChunk{ chunk_type : "IHDR".to_string() }),
+        ChunkPosition::BeforeIdat => Err(PngError::MissingChunk{ chunk_type : "IDAT".to_string() }),
+    }
+}
+
+/// Copy `reader` to `writer` one chunk at a time, dropping the chunks of type
+/// `chunk_type` picked by `occurrence`. Every part of a split message goes
+/// with it. Returns the data of the dropped chunks.
+pub fn remove_stream<R: Read, W: Write>(reader: &mut PngReader<R>, writer: &mut PngWriter<W>,
+                                        chunk_type: &str, occurrence: Occurrence) -> Result<Vec<Vec<u8>>>
+{
+    let chunkt = ChunkType::from_str(chunk_type)?;
+    let mut removed = Vec::new();
+    let mut counter = MessageCounter::default();
+    for chunk in reader.by_ref()
+    {
+        let chunk = chunk?;
+        if chunk.chunk_type() == &chunkt && occurrence.matches(counter.number(chunk.data()))
+        {
+            removed.push(chunk.data);
+            continue;
+        }
+        writer.write_chunk(&chunk)?;
+    }
+    if removed.is_empty()
+    {
+        return Err(occurrence.missing(chunk_type, counter.count))
+    }
+    layout::ensure_no_new(&reader.layout_violations(), &writer.layout_violations())?;
+    Ok(removed)
+}
+
+/// The chunks at the start of `data`, the bytes after IEND, up to the first
+/// bytes that are not a chunk with a good CRC or are another IEND. Earlier
+/// pngme releases appended their messages there.
+pub fn trailing_chunks(data: &[u8]) -> Vec<Chunk>
+{
+    let mut chunks = Vec::new();
+    let mut rest = data;
+    while let Ok((chunk, None)) = Chunk::decode(rest, data.len() - rest.len(), false, |_, _| Ok(()))
+    {
+        if &chunk.chunk_type().bytes() == b"IEND"
+        {
+            break;
+        }
+        rest = &rest[12 + chunk.length() as usize..];
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+/// Copy `reader` to `writer`, moving the `trailing_chunks` after IEND back in
+/// front of it. Whatever follows them stays after IEND. Returns the chunks moved.
+pub fn restore_trailing_stream<R: Read, W: Write>(reader: &mut PngReader<R>, writer: &mut PngWriter<W>) -> Result<Vec<Chunk>>
+{
+    let mut iend = None;
+    for chunk in reader.by_ref()
+    {
+        let chunk = chunk?;
+        if &chunk.chunk_type().bytes() == b"IEND"
+        {
+            iend = Some(chunk);
+            continue;
+        }
+        writer.write_chunk(&chunk)?;
+    }
+    let Some(iend) = iend else {
+        return Err(PngError::MissingChunk{ chunk_type : "IEND".to_string() })
+    };
+    let trailing = reader.read_trailing_data()?;
+    let restored = trailing_chunks(&trailing);
+    if restored.is_empty()
+    {
+        return Err(PngError::MissingChunk{ chunk_type : "trailing".to_string() })
+    }
+    let mut moved = 0;
+    for chunk in &restored
+    {
+        writer.write_chunk(chunk)?;
+        moved += 12 + chunk.length() as usize;
+    }
+    writer.write_chunk(&iend)?;
+    writer.write_trailing_data(&trailing[moved..])?;
+    layout::ensure_no_new(&reader.layout_violations(), &writer.layout_violations())?;
+    Ok(restored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode_remove() {
+        let mut png = Png::from_chunks(Vec::new());
+        encode_message(&mut png, "ruSt", b"hello", ChunkPosition::Index(0)).unwrap();
+        assert_eq!(decode_message(&png, "ruSt").unwrap(), b"hello");
+        assert_eq!(remove_message(&mut png, "ruSt").unwrap(), [b"hello"]);
+        assert!(matches!(decode_message(&png, "ruSt"), Err(PngError::MissingChunk{ .. })));
+    }
+
+    #[test]
+    fn test_find_message() {
+        let mut png = Png::from_chunks(Vec::new());
+        encode_message(&mut png, "ruSt", b"hello", ChunkPosition::Index(0)).unwrap();
+        let bytes = png.as_bytes();
+        let mut reader = PngReader::new(&bytes[..]).unwrap();
+        assert_eq!(find_message(&mut reader, "ruSt").unwrap(), b"hello");
+        let mut reader = PngReader::new(&bytes[..]).unwrap();
+        assert!(matches!(find_message(&mut reader, "maSt"), Err(PngError::MissingChunk{ .. })));
+    }
+
+    fn testing_png() -> Png {
+        let chunks = ["IHDR", "IDAT", "IDAT", "IEND"]
+            .iter()
+            .map(|t| Chunk::new(ChunkType::from_str(t).unwrap(), t.as_bytes().to_vec()).unwrap())
+            .collect();
+        Png::from_chunks(chunks)
+    }
+
+    #[test]
+    fn test_encode_stream_matches_insert() {
+        let positions = [
+            ChunkPosition::BeforeIend,
+            ChunkPosition::AfterIhdr,
+            ChunkPosition::BeforeIdat,
+            ChunkPosition::Index(1),
+            ChunkPosition::Index(3),
+        ];
+        for position in positions
+        {
+            let mut expected = testing_png();
+            encode_message(&mut expected, "ruSt", b"hello", position).unwrap();
+
+            let bytes = testing_png().as_bytes();
+            let mut reader = PngReader::new(&bytes[..]).unwrap();
+            let mut writer = PngWriter::new(Vec::new()).unwrap();
+            encode_stream(&mut reader, &mut writer, "ruSt", b"hello", position).unwrap();
+
+            assert_eq!(writer.finish().unwrap(), expected.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_encode_refuses_invalid_layout() {
+        for position in [ChunkPosition::Index(0), ChunkPosition::Index(2), ChunkPosition::Index(4)]
+        {
+            let res = encode_message(&mut testing_png(), "ruSt", b"hello", position);
+            assert!(matches!(res, Err(PngError::InvalidLayout{ .. })));
+
+            let bytes = testing_png().as_bytes();
+            let mut reader = PngReader::new(&bytes[..]).unwrap();
+            let mut writer = PngWriter::new(Vec::new()).unwrap();
+            let res = encode_stream(&mut reader, &mut writer, "ruSt", b"hello", position);
+            assert!(matches!(res, Err(PngError::InvalidLayout{ .. })));
+        }
+
+        let bytes = testing_png().as_bytes();
+        let mut reader = PngReader::new(&bytes[..]).unwrap();
+        let mut writer = PngWriter::new(Vec::new()).unwrap();
+        let res = remove_stream(&mut reader, &mut writer, "IEND", Occurrence::First);
+        assert!(matches!(res, Err(PngError::InvalidLayout{ .. })));
+    }
+
+    #[test]
+    fn test_encode_stream_missing_anchor() {
+        let bytes = testing_png().as_bytes();
+        let mut reader = PngReader::new(&bytes[..]).unwrap();
+        let mut writer = PngWriter::new(Vec::new()).unwrap();
+        let res = encode_stream(&mut reader, &mut writer, "ruSt", b"hello", ChunkPosition::Index(5));
+        assert!(matches!(res, Err(PngError::IndexOutOfRange{ index : 5, len : 4 })));
+    }
+
+    #[test]
+    fn test_remove_stream() {
+        let mut png = testing_png();
+        encode_message(&mut png, "ruSt", b"hello", ChunkPosition::BeforeIend).unwrap();
+        let bytes = png.as_bytes();
+
+        let mut reader = PngReader::new(&bytes[..]).unwrap();
+        let mut writer = PngWriter::new(Vec::new()).unwrap();
+        assert_eq!(remove_stream(&mut reader, &mut writer, "ruSt", Occurrence::First).unwrap(), [b"hello"]);
+        assert_eq!(writer.finish().unwrap(), testing_png().as_bytes());
+    }
+
+    #[test]
+    fn test_restore_trailing_stream() {
+        let mut bytes = testing_png().as_bytes();
+        let legacy = Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"hello".to_vec()).unwrap();
+        bytes.extend(legacy.as_bytes());
+        bytes.extend(b"junk");
+        let found = trailing_chunks(&bytes[bytes.len() - 21..]);
+        assert_eq!(found.iter().map(Chunk::as_bytes).collect::<Vec<_>>(), [legacy.as_bytes()]);
+
+        let mut reader = PngReader::new(&bytes[..]).unwrap();
+        let mut writer = PngWriter::new(Vec::new()).unwrap();
+        assert_eq!(restore_trailing_stream(&mut reader, &mut writer).unwrap().len(), 1);
+        let bytes = writer.finish().unwrap();
+        let png = Png::try_from(&bytes[..]).unwrap();
+        assert_eq!(decode_message(&png, "ruSt").unwrap(), b"hello");
+        assert_eq!(png.trailing_data(), b"junk");
+
+        let mut reader = PngReader::new(&bytes[..]).unwrap();
+        let mut writer = PngWriter::new(Vec::new()).unwrap();
+        assert!(matches!(restore_trailing_stream(&mut reader, &mut writer), Err(PngError::MissingChunk{ .. })));
+    }
+
+    fn png_with_messages() -> Png {
+        let mut png = testing_png();
+        for msg in ["one", "two", "three"]
+        {
+            encode_message(&mut png, "ruSt", msg.as_bytes(), ChunkPosition::BeforeIend).unwrap();
+        }
+        png
+    }
+
+    #[test]
+    fn test_decode_occurrences() {
+        let png = png_with_messages();
+        assert_eq!(decode_messages(&png, "ruSt", Occurrence::First).unwrap(), [b"one"]);
+        assert_eq!(decode_messages(&png, "ruSt", Occurrence::Nth(2)).unwrap(), [b"three"]);
+        assert_eq!(decode_messages(&png, "ruSt", Occurrence::All).unwrap().len(), 3);
+        let res = decode_messages(&png, "ruSt", Occurrence::Nth(3));
+        assert!(matches!(res, Err(PngError::IndexOutOfRange{ index : 3, len : 3 })));
+    }
+
+    #[test]
+    fn test_find_occurrences() {
+        let bytes = png_with_messages().as_bytes();
+        for (occurrence, expected) in [(Occurrence::Nth(1), vec!["two"]), (Occurrence::All, vec!["one", "two", "three"])]
+        {
+            let mut reader = PngReader::new(&bytes[..]).unwrap();
+            let found = find_messages(&mut reader, "ruSt", occurrence).unwrap();
+            let expected: Vec<Vec<u8>> = expected.iter().map(|s| s.as_bytes().to_vec()).collect();
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn test_find_envelopes() {
+        use crate::envelope::{Encryption, TEXT_CONTENT_TYPE};
+
+        let mut png = png_with_messages();
+        for (chunk_type, msg) in [("abCd", "first"), ("wxYz", "second")]
+        {
+            let envelope = Envelope::seal(msg.as_bytes(), TEXT_CONTENT_TYPE, false, &Encryption::None).unwrap();
+            encode_message(&mut png, chunk_type, &envelope.as_bytes(), ChunkPosition::BeforeIend).unwrap();
+        }
+        let bytes = png.as_bytes();
+
+        let mut reader = PngReader::new(&bytes[..]).unwrap();
+        let found = find_envelopes(&mut reader, Occurrence::All).unwrap();
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[1].open(None, None).unwrap(), b"second");
+
+        // A split message that is not an envelope is skipped and not counted.
+        let mut png = testing_png();
+        for part in split::split(b"hello", 2).unwrap()
+        {
+            encode_message(&mut png, "ruSt", &part.as_bytes(), ChunkPosition::BeforeIend).unwrap();
+        }
+        let envelope = Envelope::seal(b"world", TEXT_CONTENT_TYPE, false, &Encryption::None).unwrap();
+        encode_message(&mut png, "abCd", &envelope.as_bytes(), ChunkPosition::BeforeIend).unwrap();
+        let bytes = png.as_bytes();
+        let mut reader = PngReader::new(&bytes[..]).unwrap();
+        let found = find_envelopes(&mut reader, Occurrence::First).unwrap();
+        assert_eq!(found[0].open(None, None).unwrap(), b"world");
+        let mut reader = PngReader::new(&bytes[..]).unwrap();
+        assert!(matches!(find_envelopes(&mut reader, Occurrence::Nth(1)), Err(PngError::IndexOutOfRange{ index : 1, len : 1 })));
+
+        let bytes = testing_png().as_bytes();
+        let mut reader = PngReader::new(&bytes[..]).unwrap();
+        assert!(matches!(find_envelopes(&mut reader, Occurrence::First), Err(PngError::MissingChunk{ .. })));
+    }
+
+    #[test]
+    fn test_remove_stream_occurrences() {
+        let bytes = png_with_messages().as_bytes();
+
+        let mut reader = PngReader::new(&bytes[..]).unwrap();
+        let mut writer = PngWriter::new(Vec::new()).unwrap();
+        assert_eq!(remove_stream(&mut reader, &mut writer, "ruSt", Occurrence::Nth(1)).unwrap(), [b"two"]);
+        let png = Png::try_from(&writer.finish().unwrap()[..]).unwrap();
+        assert_eq!(decode_messages(&png, "ruSt", Occurrence::All).unwrap(), [&b"one"[..], &b"three"[..]]);
+
+        let mut reader = PngReader::new(&bytes[..]).unwrap();
+        let mut writer = PngWriter::new(Vec::new()).unwrap();
+        assert_eq!(remove_stream(&mut reader, &mut writer, "ruSt", Occurrence::All).unwrap().len(), 3);
+        assert_eq!(writer.finish().unwrap(), testing_png().as_bytes());
+    }
+
+    #[test]
+    fn test_invalid_chunk_type() {
+        let mut png = Png::from_chunks(Vec::new());
+        let res = encode_message(&mut png, "r1St", b"hello", ChunkPosition::Index(0));
+        assert!(matches!(res, Err(PngError::InvalidChunkType{ .. })));
+    }
+
+    /// `png_with_messages` with "a split message" in 4-byte parts between "one" and "two".
+    fn png_with_split_message() -> Vec<u8> {
+        let bytes = png_with_messages().as_bytes();
+        let mut reader = PngReader::new(&bytes[..]).unwrap();
+        let mut writer = PngWriter::new(Vec::new()).unwrap();
+        encode_split_stream(&mut reader, &mut writer, "ruSt", b"a split message", 4, ChunkPosition::Index(4)).unwrap();
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn test_find_split_message() {
+        let bytes = png_with_split_message();
+        let png = Png::try_from(&bytes[..]).unwrap();
+        assert_eq!(png.chunks_by_type("ruSt").len(), 7);
+
+        let mut reader = PngReader::new(&bytes[..]).unwrap();
+        assert_eq!(find_messages(&mut reader, "ruSt", Occurrence::Nth(1)).unwrap(), [b"a split message"]);
+        let mut reader = PngReader::new(&bytes[..]).unwrap();
+        let found = find_messages(&mut reader, "ruSt", Occurrence::All).unwrap();
+        assert_eq!(found.len(), 4);
+        assert_eq!(found[2], b"two");
+    }
+
+    #[test]
+    fn test_find_split_message_missing_part() {
+        let bytes = png_with_split_message();
+        let mut chunks: Vec<Chunk> = PngReader::new(&bytes[..]).unwrap().map(|chunk| chunk.unwrap()).collect();
+        chunks.remove(6);
+        let bytes = Png::from_chunks(chunks).as_bytes();
+
+        let mut reader = PngReader::new(&bytes[..]).unwrap();
+        let res = find_messages(&mut reader, "ruSt", Occurrence::Nth(1));
+        assert!(matches!(res, Err(PngError::MissingParts{ found : 3, total : 4, .. })));
+    }
+
+    #[test]
+    fn test_split_message_in_memory() {
+        let bytes = png_with_split_message();
+        let mut png = Png::try_from(&bytes[..]).unwrap();
+        for occurrence in [Occurrence::Nth(1), Occurrence::All]
+        {
+            let mut reader = PngReader::new(&bytes[..]).unwrap();
+            assert_eq!(decode_messages(&png, "ruSt", occurrence).unwrap(), find_messages(&mut reader, "ruSt", occurrence).unwrap());
+        }
+        assert!(matches!(decode_messages(&png, "ruSt", Occurrence::Nth(4)), Err(PngError::IndexOutOfRange{ index : 4, len : 4 })));
+
+        assert_eq!(remove_messages(&mut png, "ruSt", Occurrence::Nth(1)).unwrap().len(), 4);
+        assert_eq!(png.as_bytes(), png_with_messages().as_bytes());
+    }
+
+    #[test]
+    fn test_remove_split_message() {
+        let bytes = png_with_split_message();
+        let mut reader = PngReader::new(&bytes[..]).unwrap();
+        let mut writer = PngWriter::new(Vec::new()).unwrap();
+        assert_eq!(remove_stream(&mut reader, &mut writer, "ruSt", Occurrence::Nth(1)).unwrap().len(), 4);
+        assert_eq!(writer.finish().unwrap(), png_with_messages().as_bytes());
+    }
+}
@@ -0,0 +1,192 @@
+//! Rendering of command results for `--format json`.
+//!
+//! Every run prints exactly one JSON object on stdout. On success it is
+//! `{"ok": true, "result": {...}}`, where the result fields depend on the
+//! command; on failure it is
+//! `{"ok": false, "error": {"kind": "...", "message": "...", "exit_code": N}}`.
+//! `kind` is one of the snake_case names in `error_kind`, or "usage" for a bad
+//! command line. Field names only ever get added to, never renamed.
+
+use crate::args::{self, Format};
+use clap::CommandFactory;
+use clap::error::ErrorKind;
+use pngme::Result;
+use pngme::error::{CrcMismatch, PngError};
+use pngme::ihdr::ImageHeader;
+use pngme::text::{SkippedText, TextChunk};
+use serde_json::{json, Value};
+use std::io::{self, Write};
+use std::process;
+
+/// Print the result of a command that succeeded.
+pub fn success(result: Value) -> Result<()>
+{
+    writeln!(io::stdout().lock(), "{}", json!({ "ok" : true, "result" : result }))?;
+    Ok(())
+}
+
+/// Print a failed command's error.
+pub fn failure(err: &PngError, exit_code: u8)
+{
+    print_error(error_kind(err), &err.to_string(), exit_code);
+}
+
+/// Fail with a command line usage error: clap's own message in text mode,
+/// an error object with kind "usage" in JSON mode.
+pub fn usage_error(format: Format, kind: ErrorKind, message: &str) -> !
+{
+    match format {
+        Format::Text => args::Cli::command().error(kind, message).exit(),
+        Format::Json => {
+            print_error("usage", message, 2);
+            process::exit(2)
+        }
+    }
+}
+
+/// Whether the raw command line asks for JSON, for errors clap finds before
+/// the arguments are parsed.
+pub fn json_requested(args: &[String]) -> bool
+{
+    args.iter().any(|arg| arg == "--format=json")
+        || args.windows(2).any(|pair| pair[0] == "--format" && pair[1] == "json")
+}
+
+/// The message of a clap error rendered for the terminal, on one line and
+/// without the usage and help hints after it.
+pub fn usage_message(rendered: &str) -> String
+{
+    let message = rendered.trim_start_matches("error: ");
+    let end = ["\n\nUsage:", "\n\nFor more information"].iter()
+        .filter_map(|tail| message.find(tail))
+        .min()
+        .unwrap_or(message.len());
+    message[..end].split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn print_error(kind: &str, message: &str, exit_code: u8)
+{
+    // The exit code says what went wrong even if stdout is gone.
+    let _ = writeln!(io::stdout().lock(), "{}", json!({
+        "ok" : false,
+        "error" : { "kind" : kind, "message" : message.trim_end(), "exit_code" : exit_code },
+    }));
+}
+
+/// The stable name of an error in JSON output.
+pub fn error_kind(err: &PngError) -> &'static str
+{
+    match err {
+        PngError::BadSignature{ .. } => "bad_signature",
+        PngError::TruncatedChunk{ .. } => "truncated_chunk",
+        PngError::CrcMismatch(_) => "crc_mismatch",
+        PngError::InvalidChunkType{ .. } => "invalid_chunk_type",
+        PngError::InvalidHeader{ .. } => "invalid_header",
+        PngError::InvalidLayout{ .. } => "invalid_layout",
+        PngError::MissingChunk{ .. } => "missing_chunk",
+        PngError::IndexOutOfRange{ .. } => "index_out_of_range",
+        PngError::InvalidUtf8(_) => "invalid_utf8",
+        PngError::DecryptionFailed => "decryption_failed",
+        PngError::MalformedPayload{ .. } => "malformed_payload",
+        PngError::MissingParts{ .. } => "missing_parts",
+        PngError::DuplicatePart{ .. } => "duplicate_part",
+        PngError::InvalidText{ .. } => "invalid_text",
+        PngError::MalformedText{ .. } => "malformed_text",
+        PngError::LimitExceeded{ .. } => "limit_exceeded",
+        PngError::ChunkTooLong{ .. } => "chunk_too_long",
+        PngError::TooManyChunks{ .. } => "too_many_chunks",
+        PngError::AncillaryTooLarge{ .. } => "ancillary_too_large",
+        PngError::DecompressedTooLarge{ .. } => "decompressed_too_large",
+        PngError::InvalidKey{ .. } => "invalid_key",
+        PngError::Io(_) => "io",
+    }
+}
+
+pub fn crc_mismatch(mismatch: &CrcMismatch) -> Value
+{
+    json!({
+        "index" : mismatch.index,
+        "chunk_type" : mismatch.chunk_type,
+        "offset" : mismatch.offset,
+        "expected" : mismatch.expected,
+        "actual" : mismatch.actual,
+    })
+}
+
+/// A decoded message: its text when it is valid UTF-8, and its bytes as hex always.
+pub fn message(bytes: &[u8]) -> Value
+{
+    json!({
+        "length" : bytes.len(),
+        "text" : std::str::from_utf8(bytes).ok(),
+        "hex" : hex::encode(bytes),
+    })
+}
+
+pub fn text_chunk(text: &TextChunk) -> Value
+{
+    let (compressed, language, translated_keyword) = match text {
+        TextChunk::Text{ .. } => (false, None, None),
+        TextChunk::Compressed{ .. } => (true, None, None),
+        TextChunk::International{ compressed, language, translated_keyword, .. } =>
+            (*compressed, Some(language), Some(translated_keyword)),
+    };
+    json!({
+        "chunk_type" : text.chunk_type().to_string(),
+        "keyword" : text.keyword(),
+        "text" : text.text(),
+        "compressed" : compressed,
+        "language" : language,
+        "translated_keyword" : translated_keyword,
+    })
+}
+
+pub fn skipped_text(skipped: &SkippedText) -> Value
+{
+    json!({
+        "offset" : skipped.offset,
+        "kind" : error_kind(&skipped.error),
+        "message" : skipped.error.to_string(),
+    })
+}
+
+pub fn image_header(header: &ImageHeader) -> Value
+{
+    json!({
+        "width" : header.width,
+        "height" : header.height,
+        "bit_depth" : header.bit_depth,
+        "color_type" : header.color_type as u8,
+        "color_type_name" : header.color_type.to_string(),
+        "compression_method" : header.compression_method,
+        "filter_method" : header.filter_method,
+        "interlaced" : header.interlaced,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_requested() {
+        let args = |line: &str| line.split(' ').map(String::from).collect::<Vec<_>>();
+        assert!(json_requested(&args("pngme decode --format json x.png")));
+        assert!(json_requested(&args("pngme --format=json print")));
+        assert!(!json_requested(&args("pngme decode x.png --format text")));
+    }
+
+    #[test]
+    fn test_usage_message() {
+        let rendered = "error: the following required arguments were not provided:\n  <CHUNK_TYPE>\n\n\
+                        Usage: pngme encode <FILE_PATH> <CHUNK_TYPE>\n\nFor more information, try '--help'.\n";
+        assert_eq!(usage_message(rendered), "the following required arguments were not provided: <CHUNK_TYPE>");
+        assert_eq!(usage_message("error: unexpected argument 'x' found\n"), "unexpected argument 'x' found");
+    }
+
+    #[test]
+    fn test_message() {
+        assert_eq!(message(b"hi"), json!({ "length" : 2, "text" : "hi", "hex" : "6869" }));
+        assert_eq!(message(&[0xff])["text"], Value::Null);
+    }
+}
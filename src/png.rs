@@ -3,13 +3,18 @@ use std::str::FromStr;
 use std::str;
 
 use crate::{Error, Result};
+use crate::error::{CrcMismatch, PngError};
 use crate::chunk_type::ChunkType;
 use crate::chunk::Chunk;
+use crate::ihdr::ImageHeader;
+use crate::layout::{self, LayoutViolation};
 
 #[derive(Debug)]
 pub struct Png{
-    header : [u8; 8],
+    header : Vec<u8>,
     chunk_list : Vec<Chunk>,
+    crc_errors : Vec<CrcMismatch>,
+    trailing_data : Vec<u8>,
 }
 
 impl fmt::Display for Png{
@@ -18,173 +23,333 @@ impl fmt::Display for Png{
     }
 }
 
-#[derive(PartialEq)]
-enum State{
-    Len,
-    Chunkt,
-    Data,
-    Crc,
+/// Options for `Png::parse`.
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions{
+    /// Keep chunks with a bad CRC instead of failing; they are listed in `Png::crc_errors`.
+    pub lenient : bool,
+    pub limits : ParseLimits,
 }
 
-impl TryFrom<&[u8]> for Png{
-    type Error =  Error;
-    
-
-    fn try_from(vec : &[u8]) -> Result<Self>{
-
-        let mut png = Png{
-            header : [0; 8],
-            chunk_list : Vec::new(),
-        };
-
-        let mut cnt = 0;
-
-        let mut length: usize = 0;  
-        let mut chunkt: [u8; 4] = [0; 4];
-        let mut cur_data = Vec::new(); 
-        let mut cur_crc : u32 = 0;
-
+/// Caps on what a file may make the parser hold, so untrusted input fails
+/// with an error instead of using up memory. The defaults fit any ordinary image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseLimits{
+    /// Longest chunk data accepted, in bytes.
+    pub max_chunk_length : u32,
+    /// Most chunks accepted in one file, IDAT chunks included.
+    pub max_chunk_count : usize,
+    /// Most bytes of data accepted across all ancillary chunks together,
+    /// trailing data after IEND included.
+    pub max_ancillary_bytes : u64,
+    /// Largest size compressed text or a compressed message may inflate to.
+    pub max_decompressed_size : u64,
+}
 
-        let mut cur_state = State::Len; 
-        for (i, byte) in vec.iter().enumerate()
-        {   
-            if i < 8
-            {
-                png.header[i] = *byte; 
-                continue; 
-            }
+impl Default for ParseLimits{
+    fn default() -> Self{
+        ParseLimits{
+            max_chunk_length : 256 * 1024 * 1024,
+            max_chunk_count : 1 << 18,
+            max_ancillary_bytes : 64 * 1024 * 1024,
+            max_decompressed_size : crate::envelope::DEFAULT_MAX_LEN,
+        }
+    }
+}
 
-            match cur_state {
-                State::Len => 
-                {
-                    length += (*byte as usize) << ((3 - cnt) * 8);
-                    if cnt == 3
-                    {   
-                        cur_state = State::Chunkt;
-                        cnt = 0;
-                        continue;
-                    }
-                    cnt += 1;
-                    continue; 
+impl ParseLimits{
+    /// Only the limits of the format itself, for input that is trusted.
+    pub fn unlimited() -> Self{
+        ParseLimits{
+            max_chunk_length : Chunk::MAX_LENGTH,
+            max_chunk_count : usize::MAX,
+            max_ancillary_bytes : u64::MAX,
+            max_decompressed_size : u64::MAX,
+        }
+    }
 
-                }
-                State::Chunkt => 
-                {   
-                    
-                    chunkt[cnt] = *byte; 
-                    if cnt == 3
-                    {
-                        if length != 0 {
-                            cur_state = State::Data;
-                        }
-                        else {
-                            cur_state = State::Crc;
-                        } 
-                        cnt = 0;
-                        continue;
-                    }
-                    cnt += 1; 
-                    continue; 
-                }
-                State::Data => 
-                {   
-                    cur_data.push(*byte);
-                    if cnt == length.saturating_sub(1)
-                    {   
-                        cur_state = State::Crc; 
-                        cnt = 0;
-                        continue;
-                    }
-                    cnt += 1;
-                    continue;
-                }
-                State::Crc => 
-                {
-                    cur_crc += (*byte as u32) << ((3 - cnt) * 8);
-                    if cnt == 3
-                    {   
-                        png.chunk_list.push(Chunk{
-                            len : length as u32, 
-                            chunk_type : ChunkType::try_from(chunkt).unwrap(),
-                            data : cur_data.clone(),
-                            crc : cur_crc,
-                        });
-
-                        cur_state = State::Len;
-                        cnt = 0;
-                        length = 0;
-                        chunkt = [0; 4];
-                        cur_data.clear();
-                        cur_crc = 0; 
-
-                        continue;
-                    }
-                    cnt += 1;
-                    continue; 
-                }
-            }
+    /// Check the next chunk's header against the limits before its data is
+    /// read, and count it in `usage`.
+    pub(crate) fn admit(&self, usage: &mut LimitUsage, offset: usize, chunk_type: &ChunkType, length: u32) -> Result<()>{
+        if length > self.max_chunk_length
+        {
+            return Err(PngError::ChunkTooLong{ offset, chunk_type : chunk_type.to_string(), length, max : self.max_chunk_length })
         }
-        if cnt == 3
-        {   
-            png.chunk_list.push(Chunk{
-                len : length as u32, 
-                chunk_type : ChunkType::try_from(chunkt).unwrap(),
-                data : cur_data.clone(),
-                crc : cur_crc,
-            });
-            cur_state = State::Len;
+        if usage.chunks >= self.max_chunk_count
+        {
+            return Err(PngError::TooManyChunks{ max : self.max_chunk_count })
         }
-        
-        if cur_state != State::Len
+        usage.chunks += 1;
+        if !chunk_type.is_critical()
         {
-            return Err("Chunk not aligned ".into())
+            usage.ancillary_bytes += length as u64;
+            if usage.ancillary_bytes > self.max_ancillary_bytes
+            {
+                return Err(PngError::AncillaryTooLarge{ max : self.max_ancillary_bytes })
+            }
         }
+        Ok(())
+    }
 
-        if png.header == Png::STANDARD_HEADER
+    /// Check `len` bytes of trailing data against the ancillary budget left in `usage`.
+    pub(crate) fn admit_trailing(&self, usage: &mut LimitUsage, len: u64) -> Result<()>{
+        usage.ancillary_bytes = usage.ancillary_bytes.saturating_add(len);
+        if usage.ancillary_bytes > self.max_ancillary_bytes
         {
-            return Ok(png)
+            return Err(PngError::AncillaryTooLarge{ max : self.max_ancillary_bytes })
         }
-        
-        Err("Header does not correspond".into())
+        Ok(())
+    }
+
+    /// How many more ancillary bytes `usage` leaves room for.
+    pub(crate) fn ancillary_left(&self, usage: &LimitUsage) -> u64{
+        self.max_ancillary_bytes.saturating_sub(usage.ancillary_bytes)
+    }
+}
+
+/// Whether `rest` starts with a whole chunk, length field to CRC.
+fn holds_chunk(rest: &[u8]) -> bool
+{
+    rest.len() >= 12 && rest.len() as u64 >= 12 + u32::from_be_bytes(rest[0..4].try_into().unwrap()) as u64
+}
+
+/// What a file has used up of its `ParseLimits` so far.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct LimitUsage{
+    chunks : usize,
+    ancillary_bytes : u64,
+}
+
+impl TryFrom<&[u8]> for Png{
+    type Error =  Error;
+
+    fn try_from(vec : &[u8]) -> Result<Self>{
+        Png::parse(vec, &ParseOptions::default())
     }
 }
 
+/// Where `Png::insert_chunk` places a new chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkPosition{
+    /// Right before the IEND chunk, the last place decoders still read.
+    BeforeIend,
+    /// Right after the IHDR chunk.
+    AfterIhdr,
+    /// Right before the first IDAT chunk.
+    BeforeIdat,
+    /// At a raw index into the chunk list.
+    Index(usize),
+}
+
 #[allow(unused)]
 impl Png{
     pub const STANDARD_HEADER : [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10]; 
 
     pub fn from_chunks(chunks: Vec<Chunk>) -> Png{
         Png{
-            header : Png::STANDARD_HEADER,
+            header : Png::STANDARD_HEADER.to_vec(),
             chunk_list : chunks,
+            crc_errors : Vec::new(),
+            trailing_data : Vec::new(),
         }
     }
 
+    pub fn parse(bytes: &[u8], options: &ParseOptions) -> Result<Png>{
+        Png::parse_with(bytes, options, false)
+    }
+
+    /// Parse `bytes` keeping everything exactly as it was read, so that
+    /// `as_bytes` gives back `bytes` unchanged. Any signature is accepted,
+    /// chunks with a bad CRC are kept as in lenient mode, chunk types need
+    /// not be letters, and whatever does not parse as a whole chunk, after
+    /// IEND or not, is kept as trailing data. Input shorter than a signature
+    /// is kept as a short one. Only the `limits` can make it fail.
+    pub fn parse_lossless(bytes: &[u8], limits: &ParseLimits) -> Result<Png>{
+        Png::parse_with(bytes, &ParseOptions{ lenient : true, limits : *limits }, true)
+    }
+
+    fn parse_with(bytes: &[u8], options: &ParseOptions, lossless: bool) -> Result<Png>{
+        let header_len = bytes.len().min(8);
+        if !lossless && bytes[..header_len] != Png::STANDARD_HEADER
+        {
+            return Err(PngError::BadSignature{ found : bytes[..header_len].to_vec() })
+        }
+
+        let mut png = Png::from_chunks(Vec::new());
+        png.header = bytes[..header_len].to_vec();
+        let mut usage = LimitUsage::default();
+        let mut offset = header_len;
+        while offset < bytes.len()
+        {
+            let rest = &bytes[offset..];
+            if lossless && !holds_chunk(rest)
+            {
+                options.limits.admit_trailing(&mut usage, rest.len() as u64)?;
+                png.trailing_data = rest.to_vec();
+                break;
+            }
+            let admit = |chunk_type: &ChunkType, length| options.limits.admit(&mut usage, offset, chunk_type, length);
+            let (chunk, bad_crc) = Chunk::decode(rest, offset, lossless, admit)?;
+            if let Some(expected) = bad_crc
+            {
+                let mismatch = CrcMismatch{
+                    index : png.chunk_list.len(),
+                    chunk_type : chunk.chunk_type().to_string(),
+                    offset,
+                    expected,
+                    actual : chunk.crc(),
+                };
+                if !options.lenient
+                {
+                    return Err(mismatch.into())
+                }
+                png.crc_errors.push(mismatch);
+            }
+
+            let is_iend = &chunk.chunk_type().bytes() == b"IEND";
+            offset += 12 + chunk.length() as usize;
+            png.chunk_list.push(chunk);
+            if is_iend
+            {
+                let trailing = &bytes[offset..];
+                options.limits.admit_trailing(&mut usage, trailing.len() as u64)?;
+                png.trailing_data = trailing.to_vec();
+                break;
+            }
+        }
+        Ok(png)
+    }
+
     pub fn chunks(&self) -> &[Chunk]{
         &self.chunk_list 
     }
 
-    pub fn header(&self) -> &[u8; 8]{
+    /// The signature as it was read. Only a lossless parse keeps one that is
+    /// not `STANDARD_HEADER`, which may be shorter when the input was.
+    pub fn header(&self) -> &[u8]{
         &self.header
     }
 
+    /// The image properties from the IHDR chunk.
+    pub fn header_info(&self) -> Result<ImageHeader>{
+        let ihdr = self.chunk_by_type("IHDR")
+            .ok_or_else(|| PngError::MissingChunk{ chunk_type : "IHDR".to_string() })?;
+        ImageHeader::try_from(ihdr)
+    }
+
+    /// Every way the chunk list breaks the spec's ordering and multiplicity rules.
+    pub fn validate(&self) -> Vec<LayoutViolation>{
+        layout::check(&self.chunk_list)
+    }
+
+    /// Chunks kept despite a bad CRC when parsed in lenient mode.
+    pub fn crc_errors(&self) -> &[CrcMismatch]{
+        &self.crc_errors
+    }
+
+    /// Bytes after the IEND chunk. Parsing stops at IEND, so whatever follows
+    /// it ends up here, and `as_bytes` writes it back out.
+    pub fn trailing_data(&self) -> &[u8]{
+        &self.trailing_data
+    }
+
+    pub fn set_trailing_data(&mut self, data: Vec<u8>){
+        self.trailing_data = data;
+    }
+
+    /// Remove the trailing data and return it.
+    pub fn take_trailing_data(&mut self) -> Vec<u8>{
+        std::mem::take(&mut self.trailing_data)
+    }
+
     pub fn chunk_by_type(&self, chunk_type: &str) -> Option<&Chunk>{
-        self.chunk_list.iter().find(|chunk| chunk.chunk_type() == &ChunkType::from_str(chunk_type).unwrap())
+        let chunk_type = ChunkType::from_str(chunk_type).ok()?;
+        self.chunk_list.iter().find(|chunk| chunk.chunk_type() == &chunk_type)
+    }
+
+    /// Every chunk of type `chunk_type`, in file order.
+    pub fn chunks_by_type(&self, chunk_type: &str) -> Vec<&Chunk>{
+        let Ok(chunk_type) = ChunkType::from_str(chunk_type) else { return Vec::new() };
+        self.chunk_list.iter().filter(|chunk| chunk.chunk_type() == &chunk_type).collect()
     }
 
-    pub fn append_chunk(&mut self, chunk: Chunk){
+    /// Add `chunk` at the end. Fails if that breaks the layout, as it does after IEND.
+    pub fn append_chunk(&mut self, chunk: Chunk) -> Result<()>{
+        self.check_edit(self.chunk_list.iter().chain(Some(&chunk)))?;
         self.chunk_list.push(chunk);
+        Ok(())
+    }
+
+    pub fn insert_chunk(&mut self, chunk: Chunk, position: ChunkPosition) -> Result<()>{
+        let index = match position {
+            ChunkPosition::BeforeIend => self.required_position_of("IEND")?,
+            ChunkPosition::AfterIhdr => self.required_position_of("IHDR")? + 1,
+            ChunkPosition::BeforeIdat => self.required_position_of("IDAT")?,
+            ChunkPosition::Index(i) => {
+                if i > self.chunk_list.len()
+                {
+                    return Err(PngError::IndexOutOfRange{ index : i, len : self.chunk_list.len() })
+                }
+                i
+            }
+        };
+        let (head, tail) = self.chunk_list.split_at(index);
+        self.check_edit(head.iter().chain(Some(&chunk)).chain(tail))?;
+        self.chunk_list.insert(index, chunk);
+        Ok(())
+    }
+
+    /// Refuse an edit whose resulting chunk list, `planned`, has layout
+    /// violations the current one does not.
+    fn check_edit<'a>(&self, planned: impl IntoIterator<Item = &'a Chunk>) -> Result<()>{
+        layout::ensure_no_new(&self.validate(), &layout::check(planned))
+    }
+
+    fn position_of(&self, chunk_type: &str) -> Option<usize>{
+        let chunk_type = ChunkType::from_str(chunk_type).ok()?;
+        self.chunk_list.iter().position(|chunk| chunk.chunk_type() == &chunk_type)
+    }
+
+    fn required_position_of(&self, chunk_type: &str) -> Result<usize>{
+        self.position_of(chunk_type).ok_or_else(|| PngError::MissingChunk{ chunk_type : chunk_type.to_string() })
     }
 
     pub fn remove_chunk(&mut self, chunk_type: &str) -> Result<Chunk>{
-        for (i, chunk) in self.chunk_list.iter().enumerate()
+        ChunkType::from_str(chunk_type)?;
+        let i = self.required_position_of(chunk_type)?;
+        self.check_edit(self.chunk_list[..i].iter().chain(&self.chunk_list[i + 1..]))?;
+        Ok(self.chunk_list.remove(i))
+    }
+
+    /// Remove every chunk of type `chunk_type`, failing if there is none.
+    pub fn remove_all_chunks(&mut self, chunk_type: &str) -> Result<Vec<Chunk>>{
+        let chunkt = ChunkType::from_str(chunk_type)?;
+        self.check_edit(self.chunk_list.iter().filter(|chunk| chunk.chunk_type() != &chunkt))?;
+        let (removed, kept) = std::mem::take(&mut self.chunk_list)
+            .into_iter()
+            .partition(|chunk| chunk.chunk_type() == &chunkt);
+        self.chunk_list = kept;
+        if removed.is_empty()
         {
-            if chunk.chunk_type() == &ChunkType::from_str(chunk_type).unwrap()
-            {
-                return Ok(self.chunk_list.remove(i))
-            }
+            return Err(PngError::MissingChunk{ chunk_type : chunk_type.to_string() })
         }
-        Err("Did not find element to remove".into())
+        Ok(removed)
+    }
+
+    /// Remove the chunks at `indices` and return them in file order. Fails if
+    /// that breaks the layout.
+    pub fn remove_chunks_at(&mut self, indices: &[usize]) -> Result<Vec<Chunk>>{
+        if let Some(&index) = indices.iter().find(|&&i| i >= self.chunk_list.len())
+        {
+            return Err(PngError::IndexOutOfRange{ index, len : self.chunk_list.len() })
+        }
+        self.check_edit(self.chunk_list.iter().enumerate().filter(|(i, _)| !indices.contains(i)).map(|(_, chunk)| chunk))?;
+        let (removed, kept): (Vec<_>, Vec<_>) = std::mem::take(&mut self.chunk_list)
+            .into_iter()
+            .enumerate()
+            .partition(|(i, _)| indices.contains(i));
+        self.chunk_list = kept.into_iter().map(|(_, chunk)| chunk).collect();
+        Ok(removed.into_iter().map(|(_, chunk)| chunk).collect())
     }
 
     pub fn as_bytes(&self) -> Vec<u8>{
@@ -193,6 +358,7 @@ impl Png{
         for chunk in self.chunk_list.iter(){
             bytes.extend_from_slice(&chunk.as_bytes())
         }
+        bytes.extend_from_slice(&self.trailing_data);
         bytes
     }
 }
@@ -207,13 +373,11 @@ mod tests {
     use std::convert::TryFrom;
 
     fn testing_chunks() -> Vec<Chunk> {
-        let mut chunks = Vec::new();
-
-        chunks.push(chunk_from_strings("FrSt", "I am the first chunk").unwrap());
-        chunks.push(chunk_from_strings("miDl", "I am another chunk").unwrap());
-        chunks.push(chunk_from_strings("LASt", "I am the last chunk").unwrap());
-
-        chunks
+        vec![
+            chunk_from_strings("FrSt", "I am the first chunk").unwrap(),
+            chunk_from_strings("miDl", "I am another chunk").unwrap(),
+            chunk_from_strings("LASt", "I am the last chunk").unwrap(),
+        ]
     }
 
     fn testing_png() -> Png {
@@ -226,7 +390,7 @@ mod tests {
         let chunk_type = ChunkType::from_str(chunk_type)?;
         let data: Vec<u8> = data.bytes().collect();
 
-        Ok(Chunk::new(chunk_type, data))
+        Chunk::new(chunk_type, data)
     }
 
     #[test]
@@ -296,6 +460,152 @@ mod tests {
     }
 
 
+    #[test]
+    fn test_bad_crc_rejected() {
+        let mut bytes = testing_png().as_bytes();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+
+        let mismatch = match Png::try_from(bytes.as_ref()) {
+            Err(PngError::CrcMismatch(mismatch)) => mismatch,
+            other => panic!("expected a crc mismatch, got {:?}", other),
+        };
+        assert_eq!(mismatch.index, 2);
+        assert_eq!(&mismatch.chunk_type, "LASt");
+        assert_eq!(mismatch.offset, 8 + 32 + 30);
+        assert_eq!(mismatch.actual, mismatch.expected ^ 0xff);
+    }
+
+    #[test]
+    fn test_bad_crc_lenient() {
+        let mut bytes = testing_png().as_bytes();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+
+        let options = ParseOptions{ lenient : true, ..Default::default() };
+        let png = Png::parse(&bytes, &options).unwrap();
+        assert_eq!(png.chunks().len(), 3);
+        assert_eq!(png.crc_errors().len(), 1);
+        assert_eq!(png.crc_errors()[0].index, 2);
+        assert_eq!(png.as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_truncated_chunk() {
+        let bytes = testing_png().as_bytes();
+        let png = Png::try_from(&bytes[..bytes.len() - 3]);
+        assert!(matches!(png, Err(PngError::TruncatedChunk{ offset : 70, .. })));
+    }
+
+    #[test]
+    fn test_invalid_chunk_type_offset() {
+        let mut bytes = testing_png().as_bytes();
+        // The type of miDl, the second chunk.
+        bytes[8 + 32 + 6] = b'1';
+        let res = Png::try_from(&bytes[..]);
+        assert!(matches!(res, Err(PngError::InvalidChunkType{ offset : Some(40), .. })));
+        assert_eq!(res.unwrap_err().to_string(), "Invalid chunk type \"mi1l\" at offset 40");
+    }
+
+    #[test]
+    fn test_parse_limits() {
+        let bytes = testing_png().as_bytes();
+        let parse = |limits: ParseLimits| Png::parse(&bytes, &ParseOptions{ limits, ..Default::default() });
+        let limits = ParseLimits::default();
+        assert!(parse(limits).is_ok());
+
+        let res = parse(ParseLimits{ max_chunk_length : 19, ..limits });
+        assert!(matches!(res, Err(PngError::ChunkTooLong{ offset : 8, length : 20, max : 19, .. })));
+        let res = parse(ParseLimits{ max_chunk_count : 2, ..limits });
+        assert!(matches!(res, Err(PngError::TooManyChunks{ max : 2 })));
+        // miDl is the only ancillary chunk, with 18 bytes.
+        assert!(parse(ParseLimits{ max_ancillary_bytes : 18, ..limits }).is_ok());
+        let res = parse(ParseLimits{ max_ancillary_bytes : 17, ..limits });
+        assert!(matches!(res, Err(PngError::AncillaryTooLarge{ max : 17 })));
+    }
+
+    #[test]
+    fn test_trailing_data() {
+        let mut bytes = PNG_FILE.to_vec();
+        let end = bytes.len();
+        bytes.extend_from_slice(b"\0\0\0\x04ruStjunk, not a chunk");
+        let mut png = Png::try_from(&bytes[..]).unwrap();
+        assert_eq!(png.chunks().last().unwrap().chunk_type().to_string(), "IEND");
+        assert_eq!(png.trailing_data(), &bytes[end..]);
+        assert_eq!(png.as_bytes(), bytes);
+
+        let limits = ParseLimits{ max_ancillary_bytes : 20, ..Default::default() };
+        let res = Png::parse(&bytes, &ParseOptions{ limits, ..Default::default() });
+        assert!(matches!(res, Err(PngError::AncillaryTooLarge{ .. })));
+
+        assert_eq!(png.take_trailing_data(), &bytes[end..]);
+        assert_eq!(png.as_bytes(), &bytes[..end]);
+    }
+
+    #[test]
+    fn test_lossless_round_trip() {
+        let with = |edit: &dyn Fn(&mut Vec<u8>)| {
+            let mut bytes = PNG_FILE.to_vec();
+            edit(&mut bytes);
+            bytes
+        };
+        // PNG_FILE ends with IEND, whose type starts 8 bytes from the end.
+        let iend = PNG_FILE.len() - 8;
+        let mut corpus = vec![
+            PNG_FILE.to_vec(),
+            testing_png().as_bytes(),
+            with(&|b| b[iend + 4] ^= 0xff),
+            with(&|b| b[iend..iend + 4].copy_from_slice(&[0, b'1', 0xff, b' '])),
+            with(&|b| b.extend_from_slice(b"after the end")),
+            with(&|b| b[..8].copy_from_slice(b"\x8aMNG\r\n\x1a\n")),
+            with(&|b| b[..8].copy_from_slice(b"not png!")),
+            with(&|b| b.truncate(iend + 2)),
+            with(&|b| b[iend - 4..iend].copy_from_slice(&u32::MAX.to_be_bytes())),
+            Png::STANDARD_HEADER.to_vec(),
+        ];
+        for len in 0..100
+        {
+            corpus.push(PNG_FILE[..len].to_vec());
+        }
+
+        for bytes in &corpus
+        {
+            let png = Png::parse_lossless(bytes, &ParseLimits::default()).unwrap();
+            assert_eq!(&png.as_bytes(), bytes);
+        }
+    }
+
+    #[test]
+    fn test_lossless_parse() {
+        let mut bytes = testing_png().as_bytes();
+        bytes[..8].copy_from_slice(b"not png!");
+        // Turn miDl into mi1l, which keeps its CRC wrong.
+        bytes[8 + 32 + 6] = b'1';
+        bytes.extend_from_slice(&[0, 0, 0, 5, b'c']);
+        assert!(Png::try_from(&bytes[..]).is_err());
+
+        let png = Png::parse_lossless(&bytes, &ParseLimits::default()).unwrap();
+        assert_eq!(png.header(), b"not png!");
+        assert_eq!(png.chunks().len(), 3);
+        assert_eq!(png.chunks()[1].chunk_type().bytes(), *b"mi1l");
+        assert_eq!(png.crc_errors().len(), 1);
+        assert_eq!(png.trailing_data(), [0, 0, 0, 5, b'c']);
+
+        let limits = ParseLimits{ max_ancillary_bytes : 20, ..Default::default() };
+        assert!(matches!(Png::parse_lossless(&bytes, &limits), Err(PngError::AncillaryTooLarge{ .. })));
+
+        let png = Png::parse_lossless(&bytes[..7], &ParseLimits::default()).unwrap();
+        assert_eq!(png.header(), b"not png");
+        assert!(png.chunks().is_empty());
+    }
+
+    #[test]
+    fn test_remove_missing_chunk() {
+        let mut png = testing_png();
+        let res = png.remove_chunk("TeSt");
+        assert!(matches!(res, Err(PngError::MissingChunk{ .. })));
+    }
+
     #[test]
     fn test_list_chunks() {
         let png = testing_png();
@@ -314,7 +624,7 @@ mod tests {
     #[test]
     fn test_append_chunk() {
         let mut png = testing_png();
-        png.append_chunk(chunk_from_strings("TeSt", "Message").unwrap());
+        png.append_chunk(chunk_from_strings("TeSt", "Message").unwrap()).unwrap();
         let chunk = png.chunk_by_type("TeSt").unwrap();
         assert_eq!(&chunk.chunk_type().to_string(), "TeSt");
         assert_eq!(&chunk.data_as_string().unwrap(), "Message");
@@ -323,12 +633,83 @@ mod tests {
     #[test]
     fn test_remove_chunk() {
         let mut png = testing_png();
-        png.append_chunk(chunk_from_strings("TeSt", "Message").unwrap());
+        png.append_chunk(chunk_from_strings("TeSt", "Message").unwrap()).unwrap();
         png.remove_chunk("TeSt").unwrap();
         let chunk = png.chunk_by_type("TeSt");
         assert!(chunk.is_none());
     }
 
+    #[test]
+    fn test_remove_chunks_at() {
+        let mut png = testing_png();
+        let removed = png.remove_chunks_at(&[2, 0]).unwrap();
+        assert_eq!(removed.iter().map(|chunk| chunk.chunk_type().to_string()).collect::<Vec<_>>(), ["FrSt", "LASt"]);
+        assert_eq!(png.chunks().len(), 1);
+        assert!(matches!(png.remove_chunks_at(&[1]), Err(PngError::IndexOutOfRange{ index : 1, len : 1 })));
+    }
+
+    #[test]
+    fn test_chunks_by_type() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("TeSt", "One").unwrap()).unwrap();
+        png.append_chunk(chunk_from_strings("TeSt", "Two").unwrap()).unwrap();
+        let chunks = png.chunks_by_type("TeSt");
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(&chunks[1].data_as_string().unwrap(), "Two");
+        assert!(png.chunks_by_type("NoNe").is_empty());
+    }
+
+    #[test]
+    fn test_remove_all_chunks() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("TeSt", "One").unwrap()).unwrap();
+        png.append_chunk(chunk_from_strings("miDl", "Other").unwrap()).unwrap();
+        png.append_chunk(chunk_from_strings("TeSt", "Two").unwrap()).unwrap();
+        let removed = png.remove_all_chunks("TeSt").unwrap();
+        assert_eq!(removed.len(), 2);
+        assert_eq!(png.chunks().len(), 4);
+        assert_eq!(&png.chunks()[3].data_as_string().unwrap(), "Other");
+        assert!(matches!(png.remove_all_chunks("TeSt"), Err(PngError::MissingChunk{ .. })));
+    }
+
+    #[test]
+    fn test_insert_chunk_before_iend() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.insert_chunk(chunk_from_strings("TeSt", "Message").unwrap(), ChunkPosition::BeforeIend).unwrap();
+        let chunks = png.chunks();
+        assert_eq!(&chunks[chunks.len() - 1].chunk_type().to_string(), "IEND");
+        assert_eq!(&chunks[chunks.len() - 2].chunk_type().to_string(), "TeSt");
+    }
+
+    #[test]
+    fn test_insert_chunk_after_ihdr() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.insert_chunk(chunk_from_strings("TeSt", "Message").unwrap(), ChunkPosition::AfterIhdr).unwrap();
+        assert_eq!(&png.chunks()[1].chunk_type().to_string(), "TeSt");
+    }
+
+    #[test]
+    fn test_insert_chunk_before_idat() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let idat = png.chunks().iter().position(|c| c.chunk_type().to_string() == "IDAT").unwrap();
+        png.insert_chunk(chunk_from_strings("TeSt", "Message").unwrap(), ChunkPosition::BeforeIdat).unwrap();
+        assert_eq!(&png.chunks()[idat].chunk_type().to_string(), "TeSt");
+        assert_eq!(&png.chunks()[idat + 1].chunk_type().to_string(), "IDAT");
+    }
+
+    #[test]
+    fn test_insert_chunk_missing_anchor() {
+        let mut png = testing_png();
+        let res = png.insert_chunk(chunk_from_strings("TeSt", "Message").unwrap(), ChunkPosition::BeforeIend);
+        assert!(res.is_err());
+
+        let res = png.insert_chunk(chunk_from_strings("TeSt", "Message").unwrap(), ChunkPosition::Index(4));
+        assert!(res.is_err());
+
+        png.insert_chunk(chunk_from_strings("TeSt", "Message").unwrap(), ChunkPosition::Index(3)).unwrap();
+        assert_eq!(&png.chunks()[3].chunk_type().to_string(), "TeSt");
+    }
+
     #[test]
     fn test_png_from_image_file() {
         let png = Png::try_from(&PNG_FILE[..]);
@@ -339,7 +720,7 @@ mod tests {
     fn test_as_bytes() {
         let png = Png::try_from(&PNG_FILE[..]).unwrap();
         let actual = png.as_bytes();
-        let expected: Vec<u8> = PNG_FILE.iter().copied().collect();
+        let expected: Vec<u8> = PNG_FILE.to_vec();
         assert_eq!(actual, expected);
     }
 
@@ -607,5 +988,38 @@ mod tests {
         202, 28, 31, 66, 176, 235, 16, 0, 0, 0, 3, 82, 117, 83, 116, 104, 101, 121, 158, 176, 245,
         160, 0, 0, 0, 0, 73, 69, 78, 68, 174, 66, 96, 130,
     ];
-}
 
+    #[test]
+    fn test_header_info() {
+        let mut png = testing_png();
+        assert!(matches!(png.header_info(), Err(PngError::MissingChunk{ .. })));
+
+        let ihdr = Chunk::new(ChunkType::from_str("IHDR").unwrap(), vec![0, 0, 0, 2, 0, 0, 0, 3, 8, 2, 0, 0, 1]).unwrap();
+        png.insert_chunk(ihdr, ChunkPosition::Index(0)).unwrap();
+        let header = png.header_info().unwrap();
+        assert_eq!((header.width, header.height, header.bit_depth), (2, 3, 8));
+        assert!(header.interlaced);
+    }
+
+    #[test]
+    fn test_validate() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        assert!(png.validate().is_empty());
+        assert_eq!(testing_png().validate().len(), 3);
+    }
+
+    #[test]
+    fn test_edits_keep_layout() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let res = png.append_chunk(chunk_from_strings("TeSt", "Message").unwrap());
+        assert!(matches!(res, Err(PngError::InvalidLayout{ .. })));
+        let res = png.insert_chunk(chunk_from_strings("TeSt", "Message").unwrap(), ChunkPosition::Index(0));
+        assert!(matches!(res, Err(PngError::InvalidLayout{ .. })));
+        assert!(matches!(png.remove_chunk("IEND"), Err(PngError::InvalidLayout{ .. })));
+        assert!(matches!(png.remove_all_chunks("IDAT"), Err(PngError::InvalidLayout{ .. })));
+        assert_eq!(png.as_bytes(), PNG_FILE);
+
+        let iend = chunk_from_strings("IEND", "").unwrap();
+        assert!(matches!(png.insert_chunk(iend, ChunkPosition::BeforeIend), Err(PngError::InvalidLayout{ .. })));
+    }
+}
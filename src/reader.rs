@@ -0,0 +1,337 @@
+use std::io::{self, Read};
+
+use crc::Crc;
+
+use crate::Result;
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::error::{CrcMismatch, PngError};
+use crate::layout::{LayoutChecker, LayoutViolation};
+use crate::png::{LimitUsage, ParseLimits, ParseOptions, Png};
+
+const IDAT : [u8; 4] = *b"IDAT";
+const IEND : [u8; 4] = *b"IEND";
+
+/// Reads a png one chunk at a time instead of loading the whole file.
+///
+/// The signature is checked by `new`; the chunks are then yielded by the
+/// `Iterator` impl. Iteration stops after the first error, and after IEND:
+/// anything past it is left for `read_trailing_data`.
+pub struct PngReader<R: Read>{
+    inner : R,
+    options : ParseOptions,
+    skip_idat : bool,
+    offset : usize,
+    index : usize,
+    usage : LimitUsage,
+    crc_errors : Vec<CrcMismatch>,
+    layout : LayoutChecker,
+    seen_iend : bool,
+    done : bool,
+}
+
+impl<R: Read> PngReader<R>{
+    pub fn new(inner: R) -> Result<Self>{
+        PngReader::with_options(inner, ParseOptions::default())
+    }
+
+    pub fn with_options(mut inner: R, options: ParseOptions) -> Result<Self>{
+        let mut header = [0; 8];
+        let read = read_full(&mut inner, &mut header)?;
+        if header[..read] != Png::STANDARD_HEADER
+        {
+            return Err(PngError::BadSignature{ found : header[..read].to_vec() })
+        }
+        Ok(PngReader{
+            inner,
+            options,
+            skip_idat : false,
+            offset : 8,
+            index : 0,
+            usage : LimitUsage::default(),
+            crc_errors : Vec::new(),
+            layout : LayoutChecker::new(),
+            seen_iend : false,
+            done : false,
+        })
+    }
+
+    /// Drop IDAT chunks without buffering their payload. Their CRC is still checked.
+    pub fn skip_idat(mut self, skip: bool) -> Self{
+        self.skip_idat = skip;
+        self
+    }
+
+    /// Byte offset of the next chunk in the input.
+    pub fn offset(&self) -> usize{
+        self.offset
+    }
+
+    /// The limits this reader enforces, for parsing what is inside the chunks too.
+    pub fn limits(&self) -> &ParseLimits{
+        &self.options.limits
+    }
+
+    /// Chunks kept despite a bad CRC when reading in lenient mode.
+    pub fn crc_errors(&self) -> &[CrcMismatch]{
+        &self.crc_errors
+    }
+
+    /// Layout violations among the chunks read so far, skipped IDAT chunks included.
+    /// Required chunks count as missing until they are read.
+    pub fn layout_violations(&self) -> Vec<LayoutViolation>{
+        self.layout.violations()
+    }
+
+    /// The bytes after IEND, read once the remaining chunks have been read
+    /// and dropped. Empty when the file has no IEND. They count towards
+    /// `ParseLimits::max_ancillary_bytes`.
+    pub fn read_trailing_data(&mut self) -> Result<Vec<u8>>{
+        for chunk in self.by_ref()
+        {
+            chunk?;
+        }
+        if !self.seen_iend
+        {
+            return Ok(Vec::new())
+        }
+        let left = self.options.limits.ancillary_left(&self.usage);
+        let mut trailing = Vec::new();
+        (&mut self.inner).take(left.saturating_add(1)).read_to_end(&mut trailing)?;
+        self.options.limits.admit_trailing(&mut self.usage, trailing.len() as u64)?;
+        self.offset += trailing.len();
+        Ok(trailing)
+    }
+
+    pub fn into_inner(self) -> R{
+        self.inner
+    }
+
+    fn read_chunk(&mut self) -> Result<Option<Chunk>>{
+        loop {
+            if self.seen_iend
+            {
+                return Ok(None)
+            }
+            let offset = self.offset;
+            let mut head = [0; 8];
+            let read = read_full(&mut self.inner, &mut head)?;
+            if read == 0
+            {
+                return Ok(None)
+            }
+            if read < 8
+            {
+                return Err(PngError::TruncatedChunk{ offset, needed : 12, available : read })
+            }
+
+            let length = u32::from_be_bytes(head[0..4].try_into().unwrap());
+            let chunkt: [u8; 4] = head[4..8].try_into().unwrap();
+            let chunk_type = ChunkType::try_from(chunkt)
+                .map_err(|_| PngError::InvalidChunkType{ bytes : chunkt.to_vec(), offset : Some(offset) })?;
+            self.options.limits.admit(&mut self.usage, offset, &chunk_type, length)?;
+            let needed = (length as usize).saturating_add(12);
+
+            let crc = Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+            let mut digest = crc.digest();
+            digest.update(&chunkt);
+
+            let skip = self.skip_idat && chunkt == IDAT;
+            let mut data = Vec::new();
+            let copied = if skip
+            {
+                let mut buf = [0; 8192];
+                let mut remaining = length as usize;
+                while remaining > 0
+                {
+                    let n = self.inner.read(&mut buf[..remaining.min(8192)])?;
+                    if n == 0
+                    {
+                        break;
+                    }
+                    digest.update(&buf[..n]);
+                    remaining -= n;
+                }
+                length as usize - remaining
+            }
+            else
+            {
+                (&mut self.inner).take(length as u64).read_to_end(&mut data)?;
+                digest.update(&data);
+                data.len()
+            };
+
+            let mut crc_b = [0; 4];
+            let crc_read = if copied == length as usize { read_full(&mut self.inner, &mut crc_b)? } else { 0 };
+            if crc_read < 4
+            {
+                return Err(PngError::TruncatedChunk{ offset, needed, available : 8 + copied + crc_read })
+            }
+
+            let stored = u32::from_be_bytes(crc_b);
+            let computed = digest.finalize();
+            let index = self.index;
+            self.offset += needed;
+            self.index += 1;
+
+            if computed != stored
+            {
+                let mismatch = CrcMismatch{
+                    index,
+                    chunk_type : chunk_type.to_string(),
+                    offset,
+                    expected : computed,
+                    actual : stored,
+                };
+                if !self.options.lenient
+                {
+                    return Err(mismatch.into())
+                }
+                self.crc_errors.push(mismatch);
+            }
+            self.layout.push_data(chunkt, &data);
+            self.seen_iend = chunkt == IEND;
+
+            if !skip
+            {
+                return Ok(Some(Chunk{
+                    len : length,
+                    chunk_type,
+                    data,
+                    crc : stored,
+                }))
+            }
+        }
+    }
+}
+
+impl<R: Read> Iterator for PngReader<R>{
+    type Item = Result<Chunk>;
+
+    fn next(&mut self) -> Option<Self::Item>{
+        if self.done
+        {
+            return None
+        }
+        let res = self.read_chunk();
+        if !matches!(res, Ok(Some(_)))
+        {
+            self.done = true;
+        }
+        res.transpose()
+    }
+}
+
+/// Like `read_exact`, but reports how much was read before EOF instead of failing.
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize>{
+    let mut filled = 0;
+    while filled < buf.len()
+    {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn testing_bytes() -> Vec<u8> {
+        let chunks = vec![
+            Chunk::new(ChunkType::from_str("IHDR").unwrap(), vec![0; 13]).unwrap(),
+            Chunk::new(ChunkType::from_str("IDAT").unwrap(), vec![7; 20000]).unwrap(),
+            Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"hidden".to_vec()).unwrap(),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()).unwrap(),
+        ];
+        Png::from_chunks(chunks).as_bytes()
+    }
+
+    #[test]
+    fn test_reads_all_chunks() {
+        let bytes = testing_bytes();
+        let chunks: Vec<Chunk> = PngReader::new(&bytes[..]).unwrap().collect::<Result<_>>().unwrap();
+        let types: Vec<String> = chunks.iter().map(|c| c.chunk_type().to_string()).collect();
+        assert_eq!(types, ["IHDR", "IDAT", "ruSt", "IEND"]);
+        assert_eq!(chunks[1].data().len(), 20000);
+    }
+
+    #[test]
+    fn test_skip_idat() {
+        let bytes = testing_bytes();
+        let mut reader = PngReader::new(&bytes[..]).unwrap().skip_idat(true);
+        let types: Vec<String> = reader.by_ref().map(|c| c.unwrap().chunk_type().to_string()).collect();
+        assert_eq!(types, ["IHDR", "ruSt", "IEND"]);
+        assert_eq!(reader.offset(), bytes.len());
+    }
+
+    #[test]
+    fn test_skipped_idat_crc_checked() {
+        let mut bytes = testing_bytes();
+        bytes[8 + 25 + 8 + 100] ^= 1;
+        let res: Result<Vec<Chunk>> = PngReader::new(&bytes[..]).unwrap().skip_idat(true).collect();
+        assert!(matches!(res, Err(PngError::CrcMismatch(CrcMismatch{ index : 1, offset : 33, .. }))));
+    }
+
+    #[test]
+    fn test_bad_signature() {
+        let bytes = testing_bytes();
+        assert!(matches!(PngReader::new(&bytes[1..]), Err(PngError::BadSignature{ .. })));
+    }
+
+    #[test]
+    fn test_limits() {
+        let bytes = testing_bytes();
+        let read = |limits: ParseLimits| -> Result<Vec<Chunk>> {
+            PngReader::with_options(&bytes[..], ParseOptions{ limits, ..Default::default() }).unwrap().skip_idat(true).collect()
+        };
+        let limits = ParseLimits::default();
+        assert!(read(ParseLimits{ max_chunk_length : 20000, max_chunk_count : 4, max_ancillary_bytes : 6, ..limits }).is_ok());
+        // Skipped IDAT chunks count too.
+        assert!(matches!(read(ParseLimits{ max_chunk_length : 19999, ..limits }), Err(PngError::ChunkTooLong{ offset : 33, .. })));
+        assert!(matches!(read(ParseLimits{ max_chunk_count : 3, ..limits }), Err(PngError::TooManyChunks{ .. })));
+        assert!(matches!(read(ParseLimits{ max_ancillary_bytes : 5, ..limits }), Err(PngError::AncillaryTooLarge{ .. })));
+
+        // A huge declared length is refused before anything is allocated.
+        let mut huge = bytes.clone();
+        huge[33..37].copy_from_slice(&Chunk::MAX_LENGTH.to_be_bytes());
+        assert!(matches!(PngReader::new(&huge[..]).unwrap().nth(1), Some(Err(PngError::ChunkTooLong{ .. }))));
+    }
+
+    #[test]
+    fn test_stops_at_iend() {
+        let mut bytes = testing_bytes();
+        bytes.extend_from_slice(b"after the end");
+        let mut reader = PngReader::new(&bytes[..]).unwrap();
+        assert_eq!(reader.next().unwrap().unwrap().chunk_type().to_string(), "IHDR");
+        // The rest of the chunks are skipped over on the way.
+        assert_eq!(reader.read_trailing_data().unwrap(), b"after the end");
+        assert_eq!(reader.offset(), bytes.len());
+        assert!(reader.next().is_none());
+
+        let limits = ParseLimits{ max_ancillary_bytes : 10, ..Default::default() };
+        let mut reader = PngReader::with_options(&bytes[..], ParseOptions{ limits, ..Default::default() }).unwrap();
+        assert!(matches!(reader.read_trailing_data(), Err(PngError::AncillaryTooLarge{ max : 10 })));
+
+        let bytes = testing_bytes();
+        let mut reader = PngReader::new(&bytes[..]).unwrap();
+        assert!(reader.read_trailing_data().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_truncated() {
+        let bytes = testing_bytes();
+        let res: Result<Vec<Chunk>> = PngReader::new(&bytes[..bytes.len() - 2]).unwrap().collect();
+        assert!(matches!(res, Err(PngError::TruncatedChunk{ .. })));
+
+        let mut bytes = testing_bytes();
+        bytes[12] = b'1';
+        let res: Result<Vec<Chunk>> = PngReader::new(&bytes[..]).unwrap().collect();
+        assert!(matches!(res, Err(PngError::InvalidChunkType{ offset : Some(8), .. })));
+    }
+}
@@ -0,0 +1,261 @@
+use chacha20poly1305::aead::OsRng;
+use chacha20poly1305::aead::rand_core::RngCore;
+use crc::Crc;
+
+use crate::Result;
+use crate::chunk::Chunk;
+use crate::error::PngError;
+
+/// Starts every part of a split message.
+pub const PART_MAGIC : [u8; 4] = [0x89, b'P', b'M', b'p'];
+pub const PART_VERSION : u8 = 1;
+/// Bytes of header in front of every part's data.
+pub const PART_HEADER_LEN : usize = 25;
+const MAX_REPORTED_GAPS : usize = 16;
+
+/// One piece of a message split across several chunks.
+///
+/// Layout: magic, version, message ID (8 random bytes shared by every part),
+/// sequence number (u32, from 1), total part count (u32), CRC-32 of the whole
+/// message (u32), then this part's slice of the message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Part{
+    pub id : [u8; 8],
+    pub sequence : u32,
+    pub total : u32,
+    pub checksum : u32,
+    pub data : Vec<u8>,
+}
+
+impl Part{
+    /// Whether `data` starts with the part magic. `Part::try_from` still has to accept it.
+    pub fn is_part(data: &[u8]) -> bool{
+        data.starts_with(&PART_MAGIC)
+    }
+
+    /// The message ID of the part in `data`, read without copying the rest.
+    pub fn id_of(data: &[u8]) -> Option<[u8; 8]>{
+        if !Part::is_part(data) || data.len() < PART_HEADER_LEN
+        {
+            return None
+        }
+        data[5..13].try_into().ok()
+    }
+
+    /// The message ID as hex, for error messages.
+    pub fn id_hex(&self) -> String{
+        hex::encode(self.id)
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8>{
+        let mut bytes = Vec::with_capacity(PART_HEADER_LEN + self.data.len());
+        bytes.extend_from_slice(&PART_MAGIC);
+        bytes.push(PART_VERSION);
+        bytes.extend_from_slice(&self.id);
+        bytes.extend_from_slice(&self.sequence.to_be_bytes());
+        bytes.extend_from_slice(&self.total.to_be_bytes());
+        bytes.extend_from_slice(&self.checksum.to_be_bytes());
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+}
+
+impl TryFrom<&[u8]> for Part{
+    type Error = PngError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self>{
+        let malformed = |reason: &str| PngError::MalformedPayload{ reason : reason.to_string() };
+        if !Part::is_part(bytes)
+        {
+            return Err(malformed("not a pngme message part"))
+        }
+        if bytes.len() < PART_HEADER_LEN
+        {
+            return Err(malformed("message part header is truncated"))
+        }
+        if bytes[4] != PART_VERSION
+        {
+            return Err(PngError::MalformedPayload{ reason : format!("unknown message part version {}", bytes[4]) })
+        }
+        let part = Part{
+            id : bytes[5..13].try_into().unwrap(),
+            sequence : u32::from_be_bytes(bytes[13..17].try_into().unwrap()),
+            total : u32::from_be_bytes(bytes[17..21].try_into().unwrap()),
+            checksum : u32::from_be_bytes(bytes[21..25].try_into().unwrap()),
+            data : bytes[PART_HEADER_LEN..].to_vec(),
+        };
+        if part.sequence == 0 || part.sequence > part.total
+        {
+            return Err(PngError::MalformedPayload{ reason : format!("part {} of {} is out of sequence", part.sequence, part.total) })
+        }
+        Ok(part)
+    }
+}
+
+/// Cut `message` into parts of at most `part_size` bytes of message each,
+/// under a fresh random message ID.
+pub fn split(message: &[u8], part_size: usize) -> Result<Vec<Part>>
+{
+    if part_size == 0
+    {
+        return Err(PngError::MalformedPayload{ reason : "part size must be at least one byte".to_string() })
+    }
+    Chunk::check_length(part_size.saturating_add(PART_HEADER_LEN))?;
+    let total = message.len().div_ceil(part_size).max(1);
+    let total = u32::try_from(total)
+        .map_err(|_| PngError::LimitExceeded{ limit : "part count", max : u32::MAX as u64 })?;
+
+    let mut id = [0; 8];
+    OsRng.fill_bytes(&mut id);
+    let checksum = checksum(message);
+    let mut parts: Vec<Part> = message.chunks(part_size)
+        .zip(1..)
+        .map(|(data, sequence)| Part{ id, sequence, total, checksum, data : data.to_vec() })
+        .collect();
+    if parts.is_empty()
+    {
+        parts.push(Part{ id, sequence : 1, total, checksum, data : Vec::new() });
+    }
+    Ok(parts)
+}
+
+/// Whether `parts`, all from one message, has every sequence number at least once.
+pub fn is_complete(parts: &[Part]) -> bool
+{
+    let Some(first) = parts.first() else { return false };
+    let mut seen: Vec<u32> = parts.iter().map(|part| part.sequence).collect();
+    seen.sort_unstable();
+    seen.dedup();
+    seen.len() as u64 == first.total as u64
+}
+
+/// Put the parts of one message back together in sequence order, whatever
+/// order they come in. Missing or duplicate parts, parts from another message
+/// and a checksum that does not match the result are all errors.
+pub fn join(mut parts: Vec<Part>) -> Result<Vec<u8>>
+{
+    let Some(first) = parts.first() else {
+        return Err(PngError::MalformedPayload{ reason : "no message parts to join".to_string() })
+    };
+    let (id, total, expected) = (first.id, first.total, first.checksum);
+    if parts.iter().any(|part| part.id != id || part.total != total || part.checksum != expected
+                                || part.sequence == 0 || part.sequence > total)
+    {
+        return Err(PngError::MalformedPayload{ reason : format!("parts of message {} disagree on its size or checksum", first.id_hex()) })
+    }
+
+    parts.sort_by_key(|part| part.sequence);
+    if let Some(pair) = parts.windows(2).find(|pair| pair[0].sequence == pair[1].sequence)
+    {
+        return Err(PngError::DuplicatePart{ message_id : pair[0].id_hex(), sequence : pair[0].sequence })
+    }
+    if parts.len() as u64 != total as u64
+    {
+        // The total comes from the file, so only name the first few gaps.
+        let missing = (1..=total)
+            .filter(|sequence| parts.binary_search_by_key(sequence, |part| part.sequence).is_err())
+            .take(MAX_REPORTED_GAPS)
+            .collect();
+        return Err(PngError::MissingParts{ message_id : hex::encode(id), missing, found : parts.len() as u32, total })
+    }
+
+    let message: Vec<u8> = parts.into_iter().flat_map(|part| part.data).collect();
+    if checksum(&message) != expected
+    {
+        return Err(PngError::MalformedPayload{ reason : "reassembled message does not match its checksum".to_string() })
+    }
+    Ok(message)
+}
+
+fn checksum(message: &[u8]) -> u32
+{
+    Crc::<u32>::new(&crc::CRC_32_ISO_HDLC).checksum(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parts_of(message: &[u8], part_size: usize) -> Vec<Part>{
+        split(message, part_size).unwrap()
+            .iter()
+            .map(|part| Part::try_from(&part.as_bytes()[..]).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_split_join() {
+        let parts = parts_of(b"hello world", 4);
+        assert_eq!(parts.len(), 3);
+        assert!(parts.iter().all(|part| part.total == 3 && part.id == parts[0].id));
+        assert_eq!(parts[2].data, b"rld");
+        assert!(is_complete(&parts));
+        assert_eq!(join(parts).unwrap(), b"hello world");
+    }
+
+    #[test]
+    fn test_join_out_of_order() {
+        let mut parts = parts_of(b"hello world", 2);
+        parts.reverse();
+        parts.swap(1, 4);
+        assert_eq!(join(parts).unwrap(), b"hello world");
+    }
+
+    #[test]
+    fn test_empty_message() {
+        let parts = parts_of(b"", 4);
+        assert_eq!(parts.len(), 1);
+        assert_eq!(join(parts).unwrap(), b"");
+    }
+
+    #[test]
+    fn test_missing_and_duplicate() {
+        let mut parts = parts_of(b"hello world", 2);
+        parts.remove(3);
+        parts.remove(1);
+        assert!(!is_complete(&parts));
+        match join(parts.clone()) {
+            Err(PngError::MissingParts{ missing, found, total, .. }) => {
+                assert_eq!(missing, vec![2, 4]);
+                assert_eq!(found, 4);
+                assert_eq!(total, 6);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        parts.push(parts[0].clone());
+        assert!(matches!(join(parts), Err(PngError::DuplicatePart{ sequence : 1, .. })));
+    }
+
+    #[test]
+    fn test_bad_checksum() {
+        let mut parts = parts_of(b"hello world", 4);
+        parts[1].data[0] ^= 1;
+        assert!(matches!(join(parts), Err(PngError::MalformedPayload{ .. })));
+    }
+
+    #[test]
+    fn test_mixed_messages() {
+        let mut parts = parts_of(b"hello world", 4);
+        parts[1] = parts_of(b"hello world", 4).remove(1);
+        assert!(matches!(join(parts), Err(PngError::MalformedPayload{ .. })));
+    }
+
+    #[test]
+    fn test_huge_total() {
+        let mut parts = parts_of(b"hello", 8);
+        parts[0].total = u32::MAX;
+        match join(parts) {
+            Err(PngError::MissingParts{ missing, .. }) => assert_eq!(missing.len(), MAX_REPORTED_GAPS),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_rejects_bad_header() {
+        let mut bytes = parts_of(b"hello", 4)[0].as_bytes();
+        assert!(Part::try_from(&bytes[..20]).is_err());
+        bytes[16] = 9;
+        assert!(Part::try_from(&bytes[..]).is_err());
+    }
+}
@@ -0,0 +1,457 @@
+use std::fmt;
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+use crate::Result;
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::envelope::DEFAULT_MAX_LEN;
+use crate::error::PngError;
+use crate::layout;
+use crate::reader::PngReader;
+use crate::writer::PngWriter;
+use crate::zlib;
+
+/// One of the standard textual chunks, keyed by its keyword.
+///
+/// Keywords are 1 to 79 printable Latin-1 characters with no leading,
+/// trailing or doubled spaces, and are compared case-sensitively.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TextChunk{
+    /// tEXt: Latin-1 text.
+    Text{ keyword : String, text : String },
+    /// zTXt: zlib-compressed Latin-1 text.
+    Compressed{ keyword : String, text : String },
+    /// iTXt: UTF-8 text, optionally compressed, with a language tag and a
+    /// translation of the keyword.
+    International{ keyword : String, compressed : bool, language : String, translated_keyword : String, text : String },
+}
+
+impl TextChunk{
+    /// Whether chunks of this type are textual chunks `TextChunk` understands.
+    pub fn is_text_type(chunk_type: &ChunkType) -> bool{
+        matches!(&chunk_type.bytes(), b"tEXt" | b"zTXt" | b"iTXt")
+    }
+
+    pub fn keyword(&self) -> &str{
+        match self {
+            TextChunk::Text{ keyword, .. }
+            | TextChunk::Compressed{ keyword, .. }
+            | TextChunk::International{ keyword, .. } => keyword,
+        }
+    }
+
+    pub fn text(&self) -> &str{
+        match self {
+            TextChunk::Text{ text, .. }
+            | TextChunk::Compressed{ text, .. }
+            | TextChunk::International{ text, .. } => text,
+        }
+    }
+
+    pub fn chunk_type(&self) -> ChunkType{
+        let name = match self {
+            TextChunk::Text{ .. } => "tEXt",
+            TextChunk::Compressed{ .. } => "zTXt",
+            TextChunk::International{ .. } => "iTXt",
+        };
+        ChunkType::from_str(name).unwrap()
+    }
+
+    /// Encode as a chunk, checking the keyword and that the text fits the chunk's encoding.
+    pub fn to_chunk(&self) -> Result<Chunk>{
+        let mut data = encode_keyword(self.keyword())?;
+        data.push(0);
+        match self {
+            TextChunk::Text{ text, .. } => data.extend(encode_latin1(text, "text")?),
+            TextChunk::Compressed{ text, .. } => {
+                data.push(0);
+                data.extend(zlib::deflate(&encode_latin1(text, "text")?)?);
+            }
+            TextChunk::International{ compressed, language, translated_keyword, text, .. } => {
+                if !language.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')
+                {
+                    return Err(PngError::InvalidText{ reason : format!("language tag {:?} is not letters, digits and hyphens", language) })
+                }
+                data.push(*compressed as u8);
+                data.push(0);
+                data.extend_from_slice(language.as_bytes());
+                data.push(0);
+                data.extend_from_slice(translated_keyword.as_bytes());
+                data.push(0);
+                if *compressed
+                {
+                    data.extend(zlib::deflate(text.as_bytes())?);
+                }
+                else
+                {
+                    data.extend_from_slice(text.as_bytes());
+                }
+            }
+        }
+        Chunk::new(self.chunk_type(), data)
+    }
+}
+
+impl fmt::Display for TextChunk{
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result{
+        write!(f, "{} ({}): {}", self.keyword(), self.chunk_type(), self.text())
+    }
+}
+
+impl TextChunk{
+    /// Parse a textual chunk, inflating compressed text to at most `max_len` bytes.
+    pub fn parse(chunk: &Chunk, max_len: u64) -> Result<Self>{
+        let name = chunk.chunk_type().to_string();
+        let malformed = |reason: &str| PngError::MalformedText{ chunk_type : name.clone(), reason : reason.to_string() };
+        let inflate = |data: &[u8]| zlib::inflate(data, max_len).map_err(|err| match err {
+            PngError::MalformedPayload{ .. } => malformed("compressed text is corrupt"),
+            other => other,
+        });
+
+        let data = chunk.data();
+        let (keyword, rest) = split_at_nul(data).ok_or_else(|| malformed("keyword is not terminated"))?;
+        let keyword = decode_latin1(keyword);
+        check_keyword(&keyword).map_err(|_| malformed("keyword is not valid"))?;
+
+        match &chunk.chunk_type().bytes() {
+            b"tEXt" => Ok(TextChunk::Text{ keyword, text : decode_latin1(rest) }),
+            b"zTXt" => {
+                match rest.split_first() {
+                    Some((0, compressed)) => Ok(TextChunk::Compressed{ keyword, text : decode_latin1(&inflate(compressed)?) }),
+                    Some(_) => Err(malformed("unknown compression method")),
+                    None => Err(malformed("compression method is missing")),
+                }
+            }
+            b"iTXt" => {
+                let [flag, method, rest @ ..] = rest else { return Err(malformed("compression fields are missing")) };
+                if *flag > 1 || *method != 0
+                {
+                    return Err(malformed("unknown compression flag or method"))
+                }
+                let (language, rest) = split_at_nul(rest).ok_or_else(|| malformed("language tag is not terminated"))?;
+                let (translated, text) = split_at_nul(rest).ok_or_else(|| malformed("translated keyword is not terminated"))?;
+                let utf8 = |bytes: Vec<u8>| String::from_utf8(bytes).map_err(|_| malformed("text is not UTF-8"));
+                let text = if *flag == 1 { utf8(inflate(text)?)? } else { utf8(text.to_vec())? };
+                Ok(TextChunk::International{
+                    keyword,
+                    compressed : *flag == 1,
+                    language : utf8(language.to_vec())?,
+                    translated_keyword : utf8(translated.to_vec())?,
+                    text,
+                })
+            }
+            _ => Err(malformed("not a textual chunk")),
+        }
+    }
+}
+
+impl TryFrom<&Chunk> for TextChunk{
+    type Error = PngError;
+
+    /// `TextChunk::parse` with the default decompression limit.
+    fn try_from(chunk: &Chunk) -> Result<Self>{
+        TextChunk::parse(chunk, DEFAULT_MAX_LEN)
+    }
+}
+
+/// What `find_texts` found or `delete_text_stream` removed: the text chunks
+/// that parsed, and the ones that did not, so one broken chunk does not hide
+/// the rest.
+#[derive(Debug, Default)]
+pub struct FoundTexts{
+    pub texts : Vec<TextChunk>,
+    pub skipped : Vec<SkippedText>,
+}
+
+/// A text chunk that could not be parsed.
+#[derive(Debug)]
+pub struct SkippedText{
+    /// Byte offset of the chunk in the file.
+    pub offset : usize,
+    pub error : PngError,
+}
+
+/// The text chunks in `reader`, all of them or only those with `keyword`.
+/// A chunk that does not parse is skipped, and counts as having `keyword`
+/// if its keyword field alone matches.
+pub fn find_texts<R: Read>(reader: &mut PngReader<R>, keyword: Option<&str>) -> Result<FoundTexts>
+{
+    let max_len = reader.limits().max_decompressed_size;
+    let mut found = FoundTexts::default();
+    while let Some(chunk) = reader.next()
+    {
+        let chunk = chunk?;
+        if !TextChunk::is_text_type(chunk.chunk_type()) || keyword.is_some_and(|keyword| !has_keyword(&chunk, keyword))
+        {
+            continue;
+        }
+        match TextChunk::parse(&chunk, max_len) {
+            Ok(text) => found.texts.push(text),
+            Err(error) => found.skipped.push(SkippedText{ offset : reader.offset() - 12 - chunk.length() as usize, error }),
+        }
+    }
+    Ok(found)
+}
+
+/// Copy `reader` to `writer`, replacing every text chunk with the keyword of
+/// `text` by `text`. It goes where the first of them was, or before IEND.
+pub fn set_text_stream<R: Read, W: Write>(reader: &mut PngReader<R>, writer: &mut PngWriter<W>, text: &TextChunk) -> Result<()>
+{
+    let new = text.to_chunk()?;
+    let mut pending = Some(new);
+    for chunk in reader.by_ref()
+    {
+        let chunk = chunk?;
+        let replaced = has_keyword(&chunk, text.keyword());
+        if replaced || &chunk.chunk_type().bytes() == b"IEND"
+        {
+            if let Some(new) = pending.take()
+            {
+                writer.write_chunk(&new)?;
+            }
+        }
+        if !replaced
+        {
+            writer.write_chunk(&chunk)?;
+        }
+    }
+    if pending.is_some()
+    {
+        return Err(PngError::MissingChunk{ chunk_type : "IEND".to_string() })
+    }
+    layout::ensure_no_new(&reader.layout_violations(), &writer.layout_violations())
+}
+
+/// Copy `reader` to `writer` without the text chunks with `keyword`, whether
+/// or not their body can be read. Returns what was removed, with the chunks
+/// that could not be read in `skipped`.
+pub fn delete_text_stream<R: Read, W: Write>(reader: &mut PngReader<R>, writer: &mut PngWriter<W>, keyword: &str) -> Result<FoundTexts>
+{
+    let max_len = reader.limits().max_decompressed_size;
+    let mut removed = FoundTexts::default();
+    while let Some(chunk) = reader.next()
+    {
+        let chunk = chunk?;
+        if !has_keyword(&chunk, keyword)
+        {
+            writer.write_chunk(&chunk)?;
+            continue;
+        }
+        match TextChunk::parse(&chunk, max_len) {
+            Ok(text) => removed.texts.push(text),
+            Err(error) => removed.skipped.push(SkippedText{ offset : reader.offset() - 12 - chunk.length() as usize, error }),
+        }
+    }
+    if removed.texts.is_empty() && removed.skipped.is_empty()
+    {
+        return Err(missing_keyword(keyword))
+    }
+    layout::ensure_no_new(&reader.layout_violations(), &writer.layout_violations())?;
+    Ok(removed)
+}
+
+/// The error for a keyword no text chunk has.
+pub fn missing_keyword(keyword: &str) -> PngError
+{
+    PngError::MissingChunk{ chunk_type : format!("{:?} text", keyword) }
+}
+
+/// Whether `chunk` is a text chunk with `keyword`, judged from the keyword
+/// alone so a chunk with a broken body can still be replaced or deleted.
+fn has_keyword(chunk: &Chunk, keyword: &str) -> bool
+{
+    TextChunk::is_text_type(chunk.chunk_type())
+        && split_at_nul(chunk.data()).is_some_and(|(found, _)| decode_latin1(found) == keyword)
+}
+
+fn split_at_nul(data: &[u8]) -> Option<(&[u8], &[u8])>
+{
+    let nul = data.iter().position(|b| *b == 0)?;
+    Some((&data[..nul], &data[nul + 1..]))
+}
+
+fn check_keyword(keyword: &str) -> Result<()>
+{
+    let invalid = |why: &str| Err(PngError::InvalidText{ reason : format!("keyword {:?} {}", keyword, why) });
+    let len = keyword.chars().count();
+    if len == 0 || len > 79
+    {
+        return invalid("must be 1 to 79 characters")
+    }
+    if !keyword.chars().all(|c| matches!(c as u32, 32..=126 | 161..=255))
+    {
+        return invalid("has characters outside printable Latin-1")
+    }
+    if keyword.starts_with(' ') || keyword.ends_with(' ') || keyword.contains("  ")
+    {
+        return invalid("has leading, trailing or doubled spaces")
+    }
+    Ok(())
+}
+
+fn encode_keyword(keyword: &str) -> Result<Vec<u8>>
+{
+    check_keyword(keyword)?;
+    encode_latin1(keyword, "keyword")
+}
+
+fn encode_latin1(text: &str, what: &str) -> Result<Vec<u8>>
+{
+    text.chars()
+        .map(|c| u8::try_from(c).map_err(|_| PngError::InvalidText{ reason : format!("{} has {:?}, which is not Latin-1", what, c) }))
+        .collect()
+}
+
+fn decode_latin1(bytes: &[u8]) -> String
+{
+    bytes.iter().map(|b| *b as char).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::png::{ChunkPosition, Png};
+
+    fn round_trip(text: TextChunk) {
+        let chunk = text.to_chunk().unwrap();
+        assert_eq!(TextChunk::try_from(&chunk).unwrap(), text);
+    }
+
+    #[test]
+    fn test_round_trips() {
+        round_trip(TextChunk::Text{ keyword : "Author".to_string(), text : "Jos\u{e9}".to_string() });
+        round_trip(TextChunk::Compressed{ keyword : "Comment".to_string(), text : "la ".repeat(200) });
+        round_trip(TextChunk::International{
+            keyword : "Title".to_string(),
+            compressed : true,
+            language : "ja".to_string(),
+            translated_keyword : "\u{30bf}\u{30a4}\u{30c8}\u{30eb}".to_string(),
+            text : "\u{732b}".to_string(),
+        });
+    }
+
+    #[test]
+    fn test_text_layout() {
+        let chunk = TextChunk::Text{ keyword : "Author".to_string(), text : "\u{e9}".to_string() }.to_chunk().unwrap();
+        assert_eq!(chunk.chunk_type().to_string(), "tEXt");
+        assert_eq!(chunk.data(), b"Author\0\xe9");
+    }
+
+    #[test]
+    fn test_rejects_bad_keywords() {
+        for keyword in ["", " lead", "trail ", "two  spaces", &"k".repeat(80), "snow\u{2603}"]
+        {
+            let text = TextChunk::Text{ keyword : keyword.to_string(), text : String::new() };
+            assert!(matches!(text.to_chunk(), Err(PngError::InvalidText{ .. })), "{:?}", keyword);
+        }
+        let text = TextChunk::Text{ keyword : "Title".to_string(), text : "\u{732b}".to_string() };
+        assert!(matches!(text.to_chunk(), Err(PngError::InvalidText{ .. })));
+    }
+
+    #[test]
+    fn test_rejects_malformed_chunks() {
+        let chunks = [
+            ("tEXt", &b"no terminator"[..]),
+            ("zTXt", b"Comment\0\x01xx"),
+            ("zTXt", b"Comment\0\0not zlib"),
+            ("iTXt", b"Title\0\0\0en\0\0\xff"),
+        ];
+        for (chunk_type, data) in chunks
+        {
+            let chunk = Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec()).unwrap();
+            assert!(matches!(TextChunk::try_from(&chunk), Err(PngError::MalformedText{ .. })), "{:?}", data);
+        }
+    }
+
+    #[test]
+    fn test_decompression_limit() {
+        let text = TextChunk::Compressed{ keyword : "Comment".to_string(), text : "la ".repeat(200) };
+        let chunk = text.to_chunk().unwrap();
+        assert_eq!(TextChunk::parse(&chunk, 600).unwrap(), text);
+        assert!(matches!(TextChunk::parse(&chunk, 599), Err(PngError::DecompressedTooLarge{ max : 599 })));
+    }
+
+    fn testing_png() -> Vec<u8> {
+        let chunks = vec![
+            Chunk::new(ChunkType::from_str("IHDR").unwrap(), vec![0; 13]).unwrap(),
+            TextChunk::Text{ keyword : "Author".to_string(), text : "me".to_string() }.to_chunk().unwrap(),
+            Chunk::new(ChunkType::from_str("IDAT").unwrap(), vec![1; 10]).unwrap(),
+            TextChunk::Compressed{ keyword : "Comment".to_string(), text : "hi".to_string() }.to_chunk().unwrap(),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()).unwrap(),
+        ];
+        Png::from_chunks(chunks).as_bytes()
+    }
+
+    #[test]
+    fn test_find_texts() {
+        let bytes = testing_png();
+        let mut reader = PngReader::new(&bytes[..]).unwrap();
+        assert_eq!(find_texts(&mut reader, None).unwrap().texts.len(), 2);
+        let mut reader = PngReader::new(&bytes[..]).unwrap();
+        let found = find_texts(&mut reader, Some("Comment")).unwrap();
+        assert_eq!(found.texts[0].text(), "hi");
+    }
+
+    #[test]
+    fn test_find_texts_skips_malformed() {
+        let mut png = Png::try_from(&testing_png()[..]).unwrap();
+        // A zTXt chunk whose body is not zlib data, and one with no keyword at all.
+        for data in [&b"Comment\0\0not zlib"[..], b"no separator"]
+        {
+            let chunk = Chunk::new(ChunkType::from_str("zTXt").unwrap(), data.to_vec()).unwrap();
+            png.insert_chunk(chunk, ChunkPosition::BeforeIend).unwrap();
+        }
+        let bytes = png.as_bytes();
+
+        let mut reader = PngReader::new(&bytes[..]).unwrap();
+        let found = find_texts(&mut reader, None).unwrap();
+        assert_eq!(found.texts.len(), 2);
+        assert_eq!(found.skipped.len(), 2);
+        assert_eq!(found.skipped[0].offset, bytes.len() - 12 - (12 + 12) - (12 + 17));
+        assert!(matches!(found.skipped[1].error, PngError::MalformedText{ .. }));
+
+        let mut reader = PngReader::new(&bytes[..]).unwrap();
+        let found = find_texts(&mut reader, Some("Comment")).unwrap();
+        assert_eq!((found.texts.len(), found.skipped.len()), (1, 1));
+
+        // Deleting takes the broken chunk along with the good one.
+        let mut reader = PngReader::new(&bytes[..]).unwrap();
+        let mut writer = PngWriter::new(Vec::new()).unwrap();
+        let removed = delete_text_stream(&mut reader, &mut writer, "Comment").unwrap();
+        assert_eq!((removed.texts.len(), removed.skipped.len()), (1, 1));
+        assert_eq!(removed.skipped[0].offset, found.skipped[0].offset);
+        let bytes = writer.finish().unwrap();
+        let mut reader = PngReader::new(&bytes[..]).unwrap();
+        assert!(find_texts(&mut reader, Some("Comment")).unwrap().skipped.is_empty());
+    }
+
+    #[test]
+    fn test_set_and_delete() {
+        let bytes = testing_png();
+        let new = TextChunk::International{
+            keyword : "Author".to_string(),
+            compressed : false,
+            language : String::new(),
+            translated_keyword : String::new(),
+            text : "\u{732b}".to_string(),
+        };
+        let mut reader = PngReader::new(&bytes[..]).unwrap();
+        let mut writer = PngWriter::new(Vec::new()).unwrap();
+        set_text_stream(&mut reader, &mut writer, &new).unwrap();
+        let bytes = writer.finish().unwrap();
+        let png = Png::try_from(&bytes[..]).unwrap();
+        assert_eq!(png.chunks()[1].chunk_type().to_string(), "iTXt");
+        assert_eq!(png.chunks().len(), 5);
+
+        let mut reader = PngReader::new(&bytes[..]).unwrap();
+        let mut writer = PngWriter::new(Vec::new()).unwrap();
+        assert_eq!(delete_text_stream(&mut reader, &mut writer, "Author").unwrap().texts, [new]);
+        let bytes = writer.finish().unwrap();
+
+        let mut reader = PngReader::new(&bytes[..]).unwrap();
+        let mut writer = PngWriter::new(Vec::new()).unwrap();
+        let res = delete_text_stream(&mut reader, &mut writer, "Author");
+        assert!(matches!(res, Err(PngError::MissingChunk{ .. })));
+    }
+}
@@ -0,0 +1,87 @@
+use std::io::Write;
+
+use crc::Crc;
+
+use crate::Result;
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::layout::{LayoutChecker, LayoutViolation};
+use crate::png::Png;
+
+/// Writes a png one chunk at a time, the counterpart of `PngReader`.
+///
+/// The signature is written by `new`. Each chunk's CRC is computed while its
+/// bytes are written, so nothing is concatenated into an intermediate buffer.
+pub struct PngWriter<W: Write>{
+    inner : W,
+    layout : LayoutChecker,
+}
+
+impl<W: Write> PngWriter<W>{
+    pub fn new(mut inner: W) -> Result<Self>{
+        inner.write_all(&Png::STANDARD_HEADER)?;
+        Ok(PngWriter{ inner, layout : LayoutChecker::new() })
+    }
+
+    pub fn write_chunk(&mut self, chunk: &Chunk) -> Result<()>{
+        self.write_data(chunk.chunk_type(), chunk.data())
+    }
+
+    /// Write a chunk straight from its type and data.
+    pub fn write_data(&mut self, chunk_type: &ChunkType, data: &[u8]) -> Result<()>{
+        let length = Chunk::check_length(data.len())?;
+        let chunkt_b = chunk_type.bytes();
+        let crc = Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+        let mut digest = crc.digest();
+        digest.update(&chunkt_b);
+        digest.update(data);
+
+        self.inner.write_all(&length.to_be_bytes())?;
+        self.inner.write_all(&chunkt_b)?;
+        self.inner.write_all(data)?;
+        self.inner.write_all(&digest.finalize().to_be_bytes())?;
+        self.layout.push_data(chunkt_b, data);
+        Ok(())
+    }
+
+    /// Write bytes that are not a chunk, such as trailing data read from after
+    /// another file's IEND.
+    pub fn write_trailing_data(&mut self, data: &[u8]) -> Result<()>{
+        Ok(self.inner.write_all(data)?)
+    }
+
+    /// Layout violations among the chunks written so far.
+    pub fn layout_violations(&self) -> Vec<LayoutViolation>{
+        self.layout.violations()
+    }
+
+    /// Flush and hand back the underlying writer.
+    pub fn finish(mut self) -> Result<W>{
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_writes_same_bytes_as_png() {
+        let chunks = vec![
+            Chunk::new(ChunkType::from_str("IHDR").unwrap(), vec![1; 13]).unwrap(),
+            Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"hidden".to_vec()).unwrap(),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()).unwrap(),
+        ];
+
+        let mut writer = PngWriter::new(Vec::new()).unwrap();
+        for chunk in chunks.iter()
+        {
+            writer.write_chunk(chunk).unwrap();
+        }
+        let bytes = writer.finish().unwrap();
+
+        assert_eq!(bytes, Png::from_chunks(chunks).as_bytes());
+    }
+}
@@ -0,0 +1,33 @@
+use std::io::{Read, Write};
+
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+
+use crate::Result;
+use crate::error::PngError;
+
+/// Compress `data` into a zlib stream.
+pub fn deflate(data: &[u8]) -> Result<Vec<u8>>
+{
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+/// Decompress the zlib stream `data`, refusing to produce more than `max_len`
+/// bytes. A stream that is not valid zlib is a `MalformedPayload`, one that
+/// inflates further is `DecompressedTooLarge`.
+pub fn inflate(data: &[u8], max_len: u64) -> Result<Vec<u8>>
+{
+    let mut inflated = Vec::new();
+    ZlibDecoder::new(data)
+        .take(max_len.saturating_add(1))
+        .read_to_end(&mut inflated)
+        .map_err(|_| PngError::MalformedPayload{ reason : "compressed data is corrupt".to_string() })?;
+    if inflated.len() as u64 > max_len
+    {
+        return Err(PngError::DecompressedTooLarge{ max : max_len })
+    }
+    Ok(inflated)
+}
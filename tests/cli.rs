@@ -0,0 +1,176 @@
+//! Runs the pngme binary the way a user would.
+
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+use pngme::{Chunk, ChunkType, Png};
+
+fn pngme(dir: &Path, args: &[&str]) -> Output
+{
+    Command::new(env!("CARGO_BIN_EXE_pngme"))
+        .current_dir(dir)
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// A copy of the sample image in a fresh directory.
+fn sample() -> tempfile::TempDir
+{
+    let dir = tempfile::tempdir().unwrap();
+    fs::copy(Path::new(env!("CARGO_MANIFEST_DIR")).join("mines.png"), dir.path().join("a.png")).unwrap();
+    dir
+}
+
+#[test]
+fn test_output_to_input_file() {
+    let dir = sample();
+    let original = fs::read(dir.path().join("a.png")).unwrap();
+
+    let out = pngme(dir.path(), &["encode", "a.png", "ruSt", "hello", "--output", "a.png"]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    let out = pngme(dir.path(), &["decode", "a.png", "ruSt", "--raw"]);
+    assert_eq!(out.stdout, b"hello");
+
+    let out = pngme(dir.path(), &["remove", "a.png", "ruSt", "-o", "./a.png"]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(fs::read(dir.path().join("a.png")).unwrap(), original);
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+}
+
+#[test]
+fn test_failed_output_keeps_files() {
+    let dir = sample();
+    fs::write(dir.path().join("b.png"), b"keep me").unwrap();
+
+    // There is no ruSt chunk to remove.
+    let out = pngme(dir.path(), &["remove", "a.png", "ruSt", "-o", "b.png"]);
+    assert_eq!(out.status.code(), Some(3));
+    assert_eq!(fs::read(dir.path().join("b.png")).unwrap(), b"keep me");
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+}
+
+#[test]
+fn test_json_usage_error() {
+    let dir = sample();
+    let out = pngme(dir.path(), &["--format", "json", "encode", "a.png", "ruSt"]);
+    assert_eq!(out.status.code(), Some(2));
+    let result: serde_json::Value = serde_json::from_slice(&out.stdout).unwrap();
+    assert_eq!(result["error"]["kind"], "usage");
+    assert_eq!(result["error"]["message"],
+               "the following required arguments were not provided: <MESSAGE|--input-file <INPUT_FILE>|--stdin>");
+}
+
+/// A png of nothing but `count` small ruSt chunks, written to many.png.
+fn many_chunks(dir: &Path, count: usize) -> Vec<u8>
+{
+    let chunks = (0..count)
+        .map(|i| Chunk::new("ruSt".parse::<ChunkType>().unwrap(), i.to_string().into_bytes()).unwrap())
+        .collect();
+    let bytes = Png::from_chunks(chunks).as_bytes();
+    fs::write(dir.join("many.png"), &bytes).unwrap();
+    bytes
+}
+
+#[test]
+fn test_print_reports_bad_crc() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut bytes = many_chunks(dir.path(), 2);
+    let last = bytes.len() - 1;
+    bytes[last] ^= 1;
+    fs::write(dir.path().join("many.png"), &bytes).unwrap();
+
+    let out = pngme(dir.path(), &["print", "many.png"]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    let listing = String::from_utf8(out.stdout).unwrap();
+    let crcs: Vec<&str> = listing.lines().skip(1).map(|line| line.split_whitespace().nth(4).unwrap()).collect();
+    assert_eq!(crcs, ["ok", "BAD"]);
+}
+
+/// Runs pngme with `args`, but stops reading its stdout after a few bytes.
+fn pngme_into_closed_pipe(dir: &Path, args: &[&str]) -> Output
+{
+    let mut child = Command::new(env!("CARGO_BIN_EXE_pngme"))
+        .current_dir(dir)
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut head = [0; 100];
+    child.stdout.take().unwrap().read_exact(&mut head).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn test_print_into_closed_pipe() {
+    let dir = tempfile::tempdir().unwrap();
+    many_chunks(dir.path(), 50_000);
+
+    let out = pngme_into_closed_pipe(dir.path(), &["print", "many.png"]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert!(out.stderr.is_empty());
+}
+
+#[test]
+fn test_text_list_into_closed_pipe() {
+    let dir = tempfile::tempdir().unwrap();
+    let chunks = (0..50_000)
+        .map(|i| Chunk::new("tEXt".parse::<ChunkType>().unwrap(), format!("Comment\0{}", i).into_bytes()).unwrap())
+        .collect();
+    fs::write(dir.path().join("txt.png"), Png::from_chunks(chunks).as_bytes()).unwrap();
+
+    let out = pngme_into_closed_pipe(dir.path(), &["text", "list", "txt.png"]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert!(out.stderr.is_empty());
+}
+
+#[test]
+fn test_empty_passphrase_from_env() {
+    let dir = sample();
+    let out = Command::new(env!("CARGO_BIN_EXE_pngme"))
+        .current_dir(dir.path())
+        .args(["encode", "a.png", "ruSt", "hello", "--encrypt"])
+        .env("PNGME_PASSPHRASE", "")
+        .output()
+        .unwrap();
+    assert_eq!(out.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("the passphrase cannot be empty"));
+}
+
+#[test]
+fn test_raw_message_like_sealed_payload() {
+    let dir = sample();
+    let out = pngme(dir.path(), &["encode", "a.png", "ruSt", "PMpk-my-note", "--raw"]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    let out = pngme(dir.path(), &["decode", "a.png", "ruSt", "--raw"]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(out.stdout, b"PMpk-my-note");
+}
+
+#[test]
+fn test_failed_edit_writes_nothing_to_stdout() {
+    let dir = sample();
+    // Nothing may go before IHDR.
+    let out = pngme(dir.path(), &["encode", "a.png", "ruSt", "hi", "--index", "0", "-o", "-"]);
+    assert_eq!(out.status.code(), Some(2));
+    assert!(out.stdout.is_empty());
+}
+
+#[test]
+fn test_restore_messages_after_iend() {
+    let dir = sample();
+    // Earlier releases put their messages after IEND, out of reach of decode.
+    let out = pngme(dir.path(), &["decode", "a.png", "dEAD"]);
+    assert_eq!(out.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("trailing restore a.png"));
+
+    let out = pngme(dir.path(), &["trailing", "restore", "a.png"]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    let out = pngme(dir.path(), &["decode", "a.png", "dEAD", "--raw"]);
+    assert_eq!(out.stdout, b"bonjour les zamis");
+    let out = pngme(dir.path(), &["trailing", "show", "a.png"]);
+    assert_eq!(out.stdout, b"No data after IEND\n");
+}